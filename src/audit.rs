@@ -0,0 +1,106 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+
+use crate::AppState;
+
+/// one line of the --audit-log
+///
+/// never put anything secret in here, like credentials or query strings
+#[derive(Serialize)]
+pub struct Event<'a> {
+    pub event: &'static str,
+    pub ip: IpAddr,
+    pub target: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<u64>,
+    pub outcome: u16,
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    time: f64,
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+pub struct Audit {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+fn open(path: &PathBuf) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+impl Audit {
+    pub fn new(path: PathBuf) -> io::Result<Self> {
+        Ok(Self {
+            file: Mutex::new(open(&path)?),
+            path,
+        })
+    }
+
+    /// start writing to a fresh file, for after the old one got rotated away
+    pub fn reopen(&self) {
+        match open(&self.path) {
+            Ok(file) => *self.file.lock().unwrap() = file,
+            Err(e) => eprintln!("error reopening {}: {}", self.path.display(), e),
+        }
+    }
+
+    pub fn log(&self, event: Event) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let mut line = serde_json::to_vec(&Line { time, event }).unwrap();
+        line.push(b'\n');
+
+        // a single write per line keeps lines whole even if we crash
+        if let Err(e) = self.file.lock().unwrap().write_all(&line) {
+            eprintln!("error writing audit log {:?}", e);
+        }
+    }
+}
+
+/// log something if the audit log is on
+pub fn log(state: &AppState, event: Event) {
+    if let Some(audit) = &state.audit {
+        audit.log(event);
+    }
+}
+
+/// every request gets logged here, so new routes cannot go unnoticed
+pub async fn middleware(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let target = format!("{} {}", req.method(), req.uri().path());
+    let res = next.run(req).await;
+    log(
+        &state,
+        Event {
+            event: "request",
+            ip: remote.ip().to_canonical(),
+            target: &target,
+            bytes: None,
+            outcome: res.status().as_u16(),
+        },
+    );
+    res
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use crate::{audit, AppState};
+
+const WINDOW: Duration = Duration::from_secs(60);
+/// how many clients we keep counters for before forgetting the quietest
+const MAX_TRACKED: usize = 4096;
+
+#[derive(Default)]
+struct Client {
+    errors: VecDeque<Instant>,
+    banned_until: Option<Instant>,
+}
+
+pub struct Bans {
+    after: usize,
+    time: Duration,
+    loopback: bool,
+    clients: Mutex<HashMap<IpAddr, Client>>,
+}
+
+impl Bans {
+    pub fn new(after: usize, time: Duration, loopback: bool) -> Self {
+        Self {
+            after,
+            time,
+            loopback,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let clients = self.clients.lock().unwrap();
+        clients
+            .get(&ip.to_canonical())
+            .and_then(|c| c.banned_until)
+            .is_some_and(|until| until > Instant::now())
+    }
+
+    /// count a client error against ip, banning it once it goes over the
+    /// threshold, returns whether that just happened
+    fn record(&self, ip: IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        if ip.is_loopback() && !self.loopback {
+            return false;
+        }
+
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        if clients.len() >= MAX_TRACKED && !clients.contains_key(&ip) {
+            clients.retain(|_, c| {
+                c.banned_until.is_some_and(|until| until > now)
+                    || c.errors.back().is_some_and(|&t| now - t < WINDOW)
+            });
+            if clients.len() >= MAX_TRACKED {
+                return false;
+            }
+        }
+
+        let client = clients.entry(ip).or_default();
+        if client.banned_until.is_some_and(|until| until <= now) {
+            client.banned_until = None;
+        }
+        while client.errors.front().is_some_and(|&t| now - t >= WINDOW) {
+            client.errors.pop_front();
+        }
+        client.errors.push_back(now);
+
+        if client.errors.len() >= self.after {
+            eprintln!(
+                "banning {} for {}s after {} client errors in a minute",
+                ip,
+                self.time.as_secs(),
+                client.errors.len()
+            );
+            client.errors.clear();
+            client.banned_until = Some(now + self.time);
+            return true;
+        }
+        false
+    }
+}
+
+pub async fn guard(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    req: Request,
+    next: Next,
+) -> Response {
+    let Some(bans) = &state.bans else {
+        return next.run(req).await;
+    };
+
+    // catches clients that got banned while holding a connection open
+    if bans.is_banned(remote.ip()) {
+        return (StatusCode::FORBIDDEN, "banned").into_response();
+    }
+
+    let res = next.run(req).await;
+    if res.status().is_client_error() && bans.record(remote.ip()) {
+        audit::log(
+            &state,
+            audit::Event {
+                event: "ban",
+                ip: remote.ip().to_canonical(),
+                target: "",
+                bytes: None,
+                outcome: res.status().as_u16(),
+            },
+        );
+    }
+    res
+}
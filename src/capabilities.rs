@@ -0,0 +1,64 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::header, response::IntoResponse};
+use serde::Serialize;
+
+use crate::{conflict, name_limit, AppState};
+
+/// what GET /capabilities tells clients, so they can adapt without
+/// probing
+#[derive(Serialize)]
+struct Capabilities<'a> {
+    /// where to POST multipart uploads, when taking them
+    upload: Option<&'a str>,
+    /// where the pwa share target is, when enabled
+    share: Option<&'static str>,
+    /// where to start resumable uploads, when enabled
+    resumable: Option<&'static str>,
+    /// where stdin is being shared, when it is
+    download: Option<String>,
+    max_upload_bytes: usize,
+    max_name_len: usize,
+    quota: Option<Quota>,
+    /// whether uploads are refused while another is in progress
+    one_at_a_time: bool,
+    checksums: bool,
+    case_insensitive: bool,
+    /// what happens to taken names unless X-On-Conflict says otherwise
+    on_conflict: conflict::Policy,
+    auth: bool,
+    tls: bool,
+}
+
+#[derive(Serialize)]
+struct Quota {
+    bytes: u64,
+    window_secs: u64,
+}
+
+pub async fn capabilities(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    let opt = &state.opt;
+    let uploads = opt.stdin.is_none();
+    let caps = Capabilities {
+        upload: uploads.then_some(opt.form_path.as_str()),
+        share: (uploads && opt.pwa).then_some("/share"),
+        resumable: opt.resumable.then_some("/session"),
+        download: opt.stdin.as_ref().map(|name| format!("/{}", name)),
+        max_upload_bytes: opt.limit * 1048576,
+        max_name_len: name_limit(opt),
+        quota: opt.ip_quota.map(|spec| Quota {
+            bytes: spec.bytes,
+            window_secs: spec.window.as_secs(),
+        }),
+        one_at_a_time: opt.stdout,
+        checksums: opt.write_checksums,
+        case_insensitive: opt.case_insensitive,
+        on_conflict: opt.on_conflict,
+        auth: false,
+        tls: false,
+    };
+    (
+        [(header::CONTENT_TYPE, "application/json")],
+        serde_json::to_string(&caps).unwrap(),
+    )
+}
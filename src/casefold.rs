@@ -0,0 +1,48 @@
+use std::{collections::HashSet, fs, io, sync::Mutex};
+
+/// lowercased names of everything in the upload directory, so names that
+/// only differ in case are caught before they collide on a case-folding
+/// filesystem later
+pub struct Names {
+    lowered: Mutex<HashSet<String>>,
+}
+
+impl Names {
+    /// seeded from the upload directory and the subdirectories in dirs
+    pub fn new<'a>(dirs: impl Iterator<Item = &'a str>) -> io::Result<Self> {
+        let mut lowered = HashSet::new();
+        for entry in fs::read_dir(".")? {
+            lowered.insert(entry?.file_name().to_string_lossy().to_lowercase());
+        }
+        for dir in dirs {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            for entry in entries {
+                let name = entry?.file_name();
+                lowered.insert(format!("{}/{}", dir, name.to_string_lossy()).to_lowercase());
+            }
+        }
+        Ok(Self {
+            lowered: Mutex::new(lowered),
+        })
+    }
+
+    /// reserve name, failing like create_new would if a case variant of
+    /// it is already there
+    pub fn claim(&self, name: &str) -> io::Result<()> {
+        if !self.lowered.lock().unwrap().insert(name.to_lowercase()) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "a file with this name, ignoring case, already exists",
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn release(&self, name: &str) {
+        self.lowered.lock().unwrap().remove(&name.to_lowercase());
+    }
+}
@@ -0,0 +1,261 @@
+use std::{fs::File, net::SocketAddr};
+
+use crate::{conflict, listen, quota, Opt};
+
+/// every startup validation lives here, so --check and a real start agree
+pub fn validate(opt: &Opt) -> Result<Vec<SocketAddr>, Vec<String>> {
+    let mut errors = vec![];
+
+    let addrs = listen::addresses(opt).unwrap_or_else(|e| {
+        errors.push(e);
+        vec![]
+    });
+
+    if opt.limit.checked_mul(1048576).is_none() {
+        errors.push(format!("upload limit of {} MiB is too large", opt.limit));
+    }
+    if opt.ban_after == Some(0) {
+        errors.push("--ban-after must be at least 1".to_string());
+    }
+    if let Some(spec) = opt.ip_quota {
+        if let Err(e) = quota::Quota::new(spec, vec![], opt.quota_file.clone()) {
+            errors.push(e);
+        }
+    }
+    if !opt.form_path.starts_with('/') {
+        errors.push("--form-path must start with a /".to_string());
+    }
+    if opt.form_path.contains(['{', '}', ':', '*', '"', '<', '>']) {
+        errors.push(format!(
+            "--form-path {:?} has unsupported characters",
+            opt.form_path
+        ));
+    }
+    if opt.form_path == "/capabilities" || opt.stdin.as_deref() == Some("capabilities") {
+        errors.push("/capabilities is taken by the capabilities endpoint".to_string());
+    }
+    if let Some(page) = &opt.root_page {
+        if opt.stdin.is_none() && opt.form_path == "/" {
+            errors.push("--root-page needs the form moved with --form-path".to_string());
+        }
+        if let Err(e) = File::open(page) {
+            errors.push(format!("opening root page {}: {}", page.display(), e));
+        }
+    }
+    if opt.resumable && opt.session_ttl.is_zero() {
+        errors.push("--session-ttl must be more than 0".to_string());
+    }
+    if opt.resumable && opt.form_path == "/session" {
+        errors.push("--form-path /session is taken by --resumable".to_string());
+    }
+    if opt.pwa
+        && ["/manifest.webmanifest", "/sw.js", "/icon.svg", "/share"]
+            .contains(&opt.form_path.as_str())
+    {
+        errors.push(format!("--form-path {} is taken by --pwa", opt.form_path));
+    }
+    if let Some(name) = &opt.stdin {
+        let url_safe = |c: char| c.is_ascii_alphanumeric() || "._~-".contains(c);
+        if name.is_empty() || name == "." || name == ".." || !name.chars().all(url_safe) {
+            errors.push(format!(
+                "--stdin name {:?} may only use letters, numbers and ._~-",
+                name
+            ));
+        }
+    }
+    if let Some(mime) = &opt.content_type {
+        if mime.parse::<mime_guess::Mime>().is_err() {
+            errors.push(format!("--type {:?} is not a valid content type", mime));
+        }
+    }
+    if opt.max_name_len < 32 {
+        errors.push("--max-name-len must be at least 32".to_string());
+    }
+    if let Some(path) = &opt.audit_log {
+        if let Err(e) = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+        {
+            errors.push(format!("opening audit log {}: {}", path.display(), e));
+        }
+    }
+    if opt.header_timeout == 0 || opt.body_timeout == 0 || opt.request_timeout == Some(0) {
+        errors.push("timeouts must be at least 1 second".to_string());
+    }
+    if opt.backlog < 1 {
+        errors.push("backlog must be at least 1".to_string());
+    }
+
+    // uploads land in the working directory, make sure we can actually write there
+    if !opt.stdout && opt.stdin.is_none() {
+        let probe = format!(".quickshare_check_{}", std::process::id());
+        match File::create_new(&probe) {
+            Ok(_) => {
+                if let Err(e) = std::fs::remove_file(&probe) {
+                    errors.push(format!("cleaning up {}: {}", probe, e));
+                }
+            }
+            Err(e) => errors.push(format!("upload directory is not writable: {}", e)),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(addrs)
+    } else {
+        Err(errors)
+    }
+}
+
+/// things that work but are likely not what was intended
+pub fn warnings(opt: &Opt) {
+    if !opt.stdout && opt.stdin.is_none() && on_tmpfs(".") {
+        eprintln!("warning uploads are kept in memory by tmpfs and will be gone after a reboot");
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn on_tmpfs(path: &str) -> bool {
+    const TMPFS_MAGIC: u32 = 0x01021994;
+    const RAMFS_MAGIC: u32 = 0x858458f6;
+
+    let path = std::ffi::CString::new(path).unwrap();
+    let mut stat = std::mem::MaybeUninit::<libc::statfs>::uninit();
+    // SAFETY: path is nul terminated and stat is only read once filled in
+    if unsafe { libc::statfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return false;
+    }
+    let stat = unsafe { stat.assume_init() };
+    // f_type is a different integer type depending on the platform, and
+    // where it is a signed 32 bit one ramfs comes out negative, so only
+    // the low 32 bits are compared
+    #[allow(clippy::unnecessary_cast)]
+    let f_type = stat.f_type as u32;
+    matches!(f_type, TMPFS_MAGIC | RAMFS_MAGIC)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn on_tmpfs(_path: &str) -> bool {
+    false
+}
+
+pub fn summary(opt: &Opt, addrs: &[SocketAddr]) {
+    for addr in addrs {
+        println!("would listen on {}", addr);
+    }
+    match &opt.stdin {
+        Some(name) => println!("sharing stdin at /{}", name),
+        None => println!("upload form at {}", opt.form_path),
+    }
+    if let Some(page) = &opt.root_page {
+        println!("showing {} at /", page.display());
+    }
+    if opt.stdout {
+        println!("writing uploads to stdout");
+    }
+    if opt.compress_downloads {
+        println!(
+            "compressing {} downloads of at least {} bytes",
+            opt.compress_types.join(", "),
+            opt.compress_min
+        );
+    }
+    for o in &opt.mime {
+        println!("serving .{} as {}", o.ext, o.mime);
+    }
+    println!("upload limit {} MiB", opt.limit);
+    println!("listen backlog {}", opt.backlog);
+    if let Some(path) = &opt.port_file {
+        println!("writing ports to {}", path.display());
+    }
+    #[cfg(unix)]
+    if opt.reuseport {
+        println!("SO_REUSEPORT enabled");
+    }
+    #[cfg(unix)]
+    if opt.fifo_mode {
+        println!("writing into existing named pipes");
+    }
+    if opt.nodelay {
+        println!("TCP_NODELAY enabled");
+    }
+    println!(
+        "{}s to send headers, uploads may stall for {}s",
+        opt.header_timeout, opt.body_timeout
+    );
+    if let Some(secs) = opt.request_timeout {
+        println!("requests are cut off after {}s", secs);
+    }
+    match opt.keepalive {
+        Some(0) => println!("keep-alive disabled"),
+        Some(secs) => println!("idle connections closed after {}s", secs),
+        None => (),
+    }
+    if opt.proxy_protocol {
+        println!("client addresses taken from PROXY protocol headers");
+    }
+    if opt.resumable {
+        println!(
+            "resumable uploads at /session, kept for {}s when untouched",
+            opt.session_ttl.as_secs()
+        );
+    }
+    if let Some(url) = &opt.mirror {
+        println!("mirroring uploads to {}", url);
+    }
+    if opt.ephemeral {
+        println!("deleting everything received on shutdown");
+    }
+    if opt.notify {
+        println!("notifying about each upload");
+    }
+    if opt.write_checksums {
+        println!("writing a .sha256 next to each upload");
+    }
+    if opt.case_insensitive {
+        println!("names that only differ in case count as taken");
+    }
+    match opt.on_conflict {
+        conflict::Policy::Error => (),
+        conflict::Policy::Rename => println!("taken names get a number added"),
+        conflict::Policy::Overwrite => println!("taken names get overwritten"),
+    }
+    if let Some(kind) = opt.validate {
+        let kind = clap::ValueEnum::to_possible_value(&kind).unwrap();
+        println!("refusing uploads that are not valid {}", kind.get_name());
+    }
+    for route in &opt.route_ext {
+        println!("routing some extensions into {}/", route.dir);
+    }
+    if let Some(dir) = &opt.route_default {
+        println!("routing everything else into {}/", dir);
+    }
+    if let Some(cmd) = &opt.name_hook {
+        println!("file names decided by {:?}", cmd);
+    }
+    if let Some(path) = &opt.audit_log {
+        println!("audit log at {}", path.display());
+    }
+    if opt.require_host {
+        println!("uploads without a Host header are rejected");
+    }
+    if !opt.allowed_host.is_empty() {
+        println!("only answering for {}", opt.allowed_host.join(", "));
+    }
+    if opt.no_cache {
+        println!("caching disabled");
+    }
+    if let Some(spec) = opt.ip_quota {
+        println!(
+            "each address may upload {} bytes per {}s",
+            spec.bytes,
+            spec.window.as_secs()
+        );
+    }
+    if let Some(after) = opt.ban_after {
+        println!(
+            "banning clients for {}s after {} errors in a minute",
+            opt.ban_time, after
+        );
+    }
+}
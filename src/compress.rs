@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use axum::http::{header, Extensions, HeaderMap, StatusCode, Version};
+use tower_http::compression::{predicate::Predicate, CompressionLayer};
+
+/// what --compress-downloads compresses when not told otherwise
+pub const DEFAULT_TYPES: &str =
+    "text/*,application/json,application/xml,application/javascript,image/svg+xml,application/wasm";
+
+fn compressible(types: &[String], content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    types.iter().any(|t| match t.strip_suffix("/*") {
+        Some(prefix) => essence
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/')),
+        None => essence == *t,
+    })
+}
+
+/// gzip or zstd compression for downloads, when the client asks for it
+pub fn layer(types: &[String], min: u64) -> CompressionLayer<impl Predicate> {
+    let types: Arc<[String]> = types.iter().map(|t| t.to_ascii_lowercase()).collect();
+    let predicate = move |status: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions| {
+        // a compressed range would not line up with the offsets asked for
+        if status != StatusCode::OK || headers.contains_key(header::CONTENT_RANGE) {
+            return false;
+        }
+        let length = headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|l| l.to_str().ok())
+            .and_then(|l| l.parse::<u64>().ok());
+        // streamed stdin has no length, and is worth compressing anyway
+        if length.is_some_and(|l| l < min) {
+            return false;
+        }
+        headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|t| t.to_str().ok())
+            .is_some_and(|t| compressible(&types, t))
+    };
+
+    CompressionLayer::new()
+        .no_br()
+        .no_deflate()
+        .compress_when(predicate)
+}
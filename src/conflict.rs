@@ -0,0 +1,53 @@
+use axum::http::HeaderMap;
+use clap::ValueEnum;
+use serde::Serialize;
+
+/// `--on-conflict`, what to do when an upload's name is already taken
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Policy {
+    /// refuse the upload with a 409
+    Error,
+    /// store it as NAME (1).EXT, NAME (2).EXT and so on
+    Rename,
+    /// replace the existing file once the upload is complete
+    Overwrite,
+}
+
+/// the policy for one request, `X-On-Conflict` may pick error or rename,
+/// but only overwrite when the server already does
+///
+/// values that make no sense are ignored rather than refused
+pub fn for_request(default: Policy, headers: &HeaderMap) -> Policy {
+    let asked = headers
+        .get("x-on-conflict")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| Policy::from_str(v.trim(), true).ok());
+    match asked {
+        Some(Policy::Overwrite) => default,
+        Some(policy) => policy,
+        None => default,
+    }
+}
+
+/// name itself, then numbered variants of it for --on-conflict rename,
+/// with the stem cut short where needed to keep them within max bytes
+pub fn candidates(name: &str, max: usize) -> impl Iterator<Item = String> + '_ {
+    let (dir, base) = match name.rsplit_once('/') {
+        Some((dir, base)) => (&name[..dir.len() + 1], base),
+        None => ("", name),
+    };
+    // a leading dot is part of the name, not an extension
+    let (stem, ext) = match base.rfind('.') {
+        Some(i) if i > 0 => base.split_at(i),
+        _ => (base, ""),
+    };
+    std::iter::once(name.to_string()).chain((1..).map(move |n| {
+        let suffix = format!(" ({}){}", n, ext);
+        let mut end = stem.len().min(max.saturating_sub(suffix.len()));
+        while !stem.is_char_boundary(end) {
+            end -= 1;
+        }
+        format!("{}{}{}", dir, &stem[..end], suffix)
+    }))
+}
@@ -0,0 +1,96 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// where --ephemeral keeps track of what it created, so a killed process
+/// can still be cleaned up after
+pub const JOURNAL: &str = ".quickshare-ephemeral";
+
+/// every file and directory created this session, one json string per
+/// line since names may contain newlines, directories ending in a /
+pub struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Journal {
+    /// start a new journal, first finishing off one a previous run left
+    pub fn new(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        cleanup(&path)?;
+        let file = OpenOptions::new().append(true).create(true).open(&path)?;
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, name: &str) {
+        let line = serde_json::to_string(name).unwrap() + "\n";
+        if let Err(e) = self.file.lock().unwrap().write_all(line.as_bytes()) {
+            eprintln!("error writing {}: {}", self.path.display(), e);
+        }
+    }
+
+    /// a directory about to be created for uploads, removed again on
+    /// cleanup if nothing else ended up in it
+    pub fn record_dir(&self, dir: &str) {
+        self.record(&format!("{}/", dir));
+    }
+
+    /// delete everything recorded so far
+    pub fn finish(&self) -> io::Result<usize> {
+        let _file = self.file.lock().unwrap();
+        cleanup(&self.path)
+    }
+}
+
+/// delete every file listed in the journal at path, then the directories
+/// that are left empty and the journal itself, returning how many files
+/// were removed
+pub fn cleanup(path: &Path) -> io::Result<usize> {
+    let journal = match fs::read_to_string(path) {
+        Ok(j) => j,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+
+    let mut removed = 0;
+    let mut dirs = vec![];
+    for name in journal.lines() {
+        let Ok(name) = serde_json::from_str::<String>(name) else {
+            continue;
+        };
+        if let Some(dir) = name.strip_suffix('/') {
+            dirs.push(dir.to_string());
+            continue;
+        }
+        match fs::remove_file(&name) {
+            Ok(()) => {
+                eprintln!("removed {}", name);
+                removed += 1;
+            }
+            // discarded uploads are already gone
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => eprintln!("error removing {}: {}", name, e),
+        }
+    }
+    // innermost first, and only when empty, as someone else may have
+    // put things in there too
+    for dir in dirs.iter().rev() {
+        match fs::remove_dir(dir) {
+            Ok(()) => eprintln!("removed {}/", dir),
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::NotFound | io::ErrorKind::DirectoryNotEmpty
+                ) => {}
+            Err(e) => eprintln!("error removing {}/: {}", dir, e),
+        }
+    }
+    fs::remove_file(path)?;
+    Ok(removed)
+}
@@ -0,0 +1,48 @@
+use std::process::Stdio;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use tokio::{io::AsyncWriteExt, process::Command};
+
+/// let the --name-hook command decide what a file should be called, it
+/// gets the proposed name on stdin and prints the final one
+pub async fn rename(cmd: &str, name: &str) -> Result<String, Response> {
+    let rejected = || (StatusCode::FORBIDDEN, "file name rejected").into_response();
+    let failed = |e: std::io::Error| {
+        eprintln!("error running name hook {:?}", e);
+        (StatusCode::INTERNAL_SERVER_ERROR, "could not run name hook").into_response()
+    };
+
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(failed)?;
+    let mut stdin = child.stdin.take().unwrap();
+    stdin
+        .write_all(format!("{}\n", name).as_bytes())
+        .await
+        .map_err(failed)?;
+    drop(stdin);
+
+    let out = child.wait_with_output().await.map_err(failed)?;
+    if !out.status.success() {
+        return Err(rejected());
+    }
+
+    // the hook does not get to escape the upload directory
+    let Ok(out) = String::from_utf8(out.stdout) else {
+        return Err(rejected());
+    };
+    let out = out.trim_end_matches('\n').replace(['/', '\0'], "");
+    if out.is_empty() || out == "." || out == ".." {
+        return Err(rejected());
+    }
+
+    Ok(out)
+}
@@ -0,0 +1,83 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use tokio::net::TcpListener;
+
+use crate::Opt;
+
+/// work out every address we should be listening on
+pub fn addresses(opt: &Opt) -> Result<Vec<SocketAddr>, String> {
+    let mut addrs = match &opt.interface {
+        Some(name) => interface_addresses(name, opt.bindhost[0].port())?,
+        None => opt.bindhost.clone(),
+    };
+
+    if opt.only_v4 {
+        addrs = addrs
+            .into_iter()
+            .filter_map(|a| match a.ip() {
+                IpAddr::V6(ip) if ip.is_unspecified() => {
+                    Some(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), a.port()))
+                }
+                IpAddr::V6(_) => None,
+                IpAddr::V4(_) => Some(a),
+            })
+            .collect();
+    } else if opt.only_v6 {
+        addrs = addrs
+            .into_iter()
+            .filter_map(|a| match a.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => {
+                    Some(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), a.port()))
+                }
+                IpAddr::V4(_) => None,
+                IpAddr::V6(_) => Some(a),
+            })
+            .collect();
+    }
+
+    addrs.sort();
+    addrs.dedup();
+    if addrs.is_empty() {
+        return Err("no addresses left to listen on".to_string());
+    }
+
+    Ok(addrs)
+}
+
+fn interface_addresses(name: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let ifaces = if_addrs::get_if_addrs().map_err(|e| format!("listing interfaces: {}", e))?;
+    let ifaces: Vec<_> = ifaces.into_iter().filter(|i| i.name == name).collect();
+    if ifaces.is_empty() {
+        return Err(format!(
+            "interface {} does not exist or has no addresses",
+            name
+        ));
+    }
+
+    Ok(ifaces
+        .into_iter()
+        .map(|i| match i.ip() {
+            // link-local addresses are useless without a scope
+            IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80 => {
+                SocketAddrV6::new(ip, port, 0, i.index.unwrap_or(0)).into()
+            }
+            ip => SocketAddr::new(ip, port),
+        })
+        .collect())
+}
+
+pub fn bind(addr: SocketAddr, opt: &Opt) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() && opt.only_v6 {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(opt.reuseport)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(opt.backlog)?;
+
+    TcpListener::from_std(socket.into())
+}
@@ -1,26 +1,413 @@
 use clap::Parser;
-use std::{fs::File, include_str, io::prelude::*, net::SocketAddr};
-use tokio::net::TcpListener;
+use futures_util::{Stream, StreamExt};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    include_str,
+    io::prelude::*,
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{sync::watch, task::JoinSet};
+
+mod audit;
+mod ban;
+mod capabilities;
+mod casefold;
+mod check;
+mod cidr;
+mod compress;
+mod conflict;
+mod ephemeral;
+mod hook;
+mod idle;
+mod listen;
+mod mimes;
+mod mirror;
+mod notify;
+mod proxy;
+mod pwa;
+mod quota;
+mod route;
+mod serve;
+mod session;
+mod sink;
+mod stdin;
+mod units;
+mod unnamed;
+mod validate;
 
 use axum::{
-    extract::{DefaultBodyLimit, Multipart},
-    http::StatusCode,
-    response::Html,
+    body::Bytes,
+    extract::{multipart::Field, ConnectInfo, DefaultBodyLimit, Multipart, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::{from_fn, from_fn_with_state, map_response, Next},
+    response::{Html, IntoResponse, Response},
     routing::{get, post},
     Router,
 };
+use tower_http::{
+    services::ServeFile,
+    timeout::{RequestBodyTimeoutLayer, TimeoutLayer},
+};
 
 #[derive(Debug, Parser)]
 #[command(about = "quickly spin up a file upload form")]
 struct Opt {
-    #[arg(short, env = "BIND", default_value = "[::]:3000")]
-    bindhost: SocketAddr,
+    #[arg(
+        short,
+        env = "BIND",
+        default_value = "[::]:3000",
+        value_delimiter = ',',
+        help = "address to listen on, may be given multiple times"
+    )]
+    bindhost: Vec<SocketAddr>,
+    #[arg(short, long, help = "listen on every address of this interface")]
+    interface: Option<String>,
+    #[arg(
+        long,
+        help = "write the port of each listener to this file, handy with port 0"
+    )]
+    port_file: Option<PathBuf>,
+    #[arg(long, conflicts_with = "only_v6", help = "only listen on IPv4")]
+    only_v4: bool,
+    #[arg(long, help = "only listen on IPv6, without accepting IPv4 too")]
+    only_v6: bool,
+    #[cfg(unix)]
+    #[arg(long, help = "let other processes listen on the same port")]
+    reuseport: bool,
+    #[arg(long, help = "disable nagle's algorithm on connections")]
+    nodelay: bool,
+    #[arg(
+        long,
+        help = "expect a PROXY protocol v1 or v2 header on every connection"
+    )]
+    proxy_protocol: bool,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "close connections idle this long between requests, 0 disables keep-alive"
+    )]
+    keepalive: Option<u64>,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value = "30",
+        help = "how long a client gets to send a request's headers"
+    )]
+    header_timeout: u64,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        default_value = "60",
+        help = "give up on an upload when its body stalls this long"
+    )]
+    body_timeout: u64,
+    #[arg(
+        long,
+        value_name = "SECONDS",
+        help = "give up on any request that takes longer than this altogether"
+    )]
+    request_timeout: Option<u64>,
+    #[arg(
+        long,
+        help = "length of the pending connection queue",
+        default_value = "1024"
+    )]
+    backlog: i32,
+    #[cfg(unix)]
+    #[arg(
+        long,
+        help = "write uploads into existing named pipes with the same name (unix only)"
+    )]
+    fifo_mode: bool,
+    #[arg(
+        long,
+        help = "append a json line for every request and upload to this file"
+    )]
+    audit_log: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "reject uploads without a Host header, which some HTTP/1.0 clients leave out"
+    )]
+    require_host: bool,
+    #[arg(
+        long,
+        help = "only answer requests for this Host, the port left out, can be given more than once"
+    )]
+    allowed_host: Vec<String>,
+    #[arg(long, help = "tell clients not to cache any response")]
+    no_cache: bool,
     #[arg(short, help = "max upload size in MiB", default_value = "1024")]
     limit: usize,
+    #[arg(long, help = "ban clients that cause this many errors within a minute")]
+    ban_after: Option<usize>,
+    #[arg(long, help = "how long bans last in seconds", default_value = "600")]
+    ban_time: u64,
+    #[arg(long, help = "allow banning loopback addresses")]
+    ban_loopback: bool,
+    #[arg(long, help = "limit how much each address may upload, like 2GiB/24h")]
+    ip_quota: Option<quota::QuotaSpec>,
+    #[arg(
+        long,
+        help = "address or range exempt from --ip-quota, may be repeated"
+    )]
+    quota_exempt: Vec<cidr::Cidr>,
+    #[arg(long, help = "keep --ip-quota usage in this file across restarts")]
+    quota_file: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "max file name length in bytes, longer names get shortened",
+        default_value = "255"
+    )]
+    max_name_len: usize,
+    #[arg(
+        long,
+        alias = "case-insensitive-names",
+        conflicts_with_all = ["stdin", "stdout"],
+        help = "refuse names that only differ in case from an existing file"
+    )]
+    case_insensitive: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["stdin", "stdout"],
+        help = "write a NAME.sha256 next to each upload for sha256sum -c"
+    )]
+    write_checksums: bool,
+    #[arg(
+        long,
+        conflicts_with = "stdin",
+        help = "show a desktop notification, or ring the terminal bell, for each upload"
+    )]
+    notify: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["stdin", "stdout"],
+        value_parser = mirror::parse_url,
+        help = "also post each upload to the quickshare form at this http:// url"
+    )]
+    mirror: Option<axum::http::Uri>,
+    #[arg(
+        long,
+        conflicts_with_all = ["stdin", "stdout"],
+        help = "delete every file received this session on ctrl-c"
+    )]
+    ephemeral: bool,
+    #[arg(
+        long,
+        help = "delete what a killed --ephemeral session left behind, then exit"
+    )]
+    ephemeral_cleanup: bool,
+    #[arg(
+        long,
+        conflicts_with_all = ["stdin", "stdout"],
+        help = "put uploads with these extensions in a subdirectory, like jpg,png=images"
+    )]
+    route_ext: Vec<route::Route>,
+    #[arg(
+        long,
+        conflicts_with_all = ["stdin", "stdout"],
+        value_parser = route::parse_dir,
+        help = "subdirectory for uploads no --route-ext matches"
+    )]
+    route_default: Option<String>,
+    #[arg(
+        long,
+        value_enum,
+        default_value = "untitled",
+        help = "what to call uploads that come without a file name"
+    )]
+    unnamed_policy: unnamed::Policy,
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with = "stdin",
+        default_value = "error",
+        help = "what to do when a name is taken, X-On-Conflict may still pick error or rename"
+    )]
+    on_conflict: conflict::Policy,
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["stdin", "stdout"],
+        help = "refuse uploads that are not valid files of this kind"
+    )]
+    validate: Option<validate::Kind>,
+    #[arg(
+        long,
+        help = "command that gets each file name on stdin and prints the one to use"
+    )]
+    name_hook: Option<String>,
+    #[arg(long, help = "where to serve the upload form", default_value = "/")]
+    form_path: String,
+    #[arg(
+        long,
+        conflicts_with_all = ["stdin", "stdout"],
+        help = "take uploads in pieces through /session, so they can be resumed"
+    )]
+    resumable: bool,
+    #[arg(
+        long,
+        requires = "resumable",
+        value_parser = units::parse_duration,
+        default_value = "1h",
+        help = "how long an untouched resumable upload is kept around"
+    )]
+    session_ttl: Duration,
+    #[arg(
+        long,
+        help = "page to show at / when the form is elsewhere or stdin is being shared"
+    )]
+    root_page: Option<PathBuf>,
+    #[arg(long, help = "let browsers install the form and share files to it")]
+    pwa: bool,
+    #[arg(
+        long,
+        value_name = "NAME",
+        help = "share what is piped in at /NAME instead of taking uploads"
+    )]
+    stdin: Option<String>,
+    #[arg(
+        long,
+        requires = "stdin",
+        help = "exit after this many downloads of stdin",
+        default_value = "1"
+    )]
+    count: usize,
+    #[arg(
+        long,
+        requires = "stdin",
+        conflicts_with = "count",
+        help = "send stdin straight to one download instead of buffering it"
+    )]
+    stream: bool,
+    #[arg(long = "type", requires = "stdin", help = "content type of stdin")]
+    content_type: Option<String>,
+    #[arg(
+        long,
+        help = "content type to serve an extension as, like json5=application/json5"
+    )]
+    mime: Vec<mimes::Override>,
+    #[arg(
+        long,
+        requires = "stdin",
+        help = "gzip or zstd downloads for clients that accept it"
+    )]
+    compress_downloads: bool,
+    #[arg(
+        long,
+        requires = "compress_downloads",
+        value_delimiter = ',',
+        default_value = compress::DEFAULT_TYPES,
+        help = "content types worth compressing, like text/* or application/json"
+    )]
+    compress_types: Vec<String>,
+    #[arg(
+        long,
+        requires = "compress_downloads",
+        value_parser = units::parse_size,
+        default_value = "1KiB",
+        help = "smallest download worth compressing"
+    )]
+    compress_min: u64,
+    #[arg(
+        long,
+        conflicts_with = "stdin",
+        help = "write uploads to stdout instead of saving them, then exit"
+    )]
+    stdout: bool,
+    #[arg(
+        long,
+        requires = "stdout",
+        help = "keep taking uploads after the first"
+    )]
+    keep_listening: bool,
+    #[arg(
+        long,
+        requires = "keep_listening",
+        help = "written to stdout after each upload"
+    )]
+    delimiter: Option<String>,
+    #[arg(long, help = "do not warn about setups that are likely mistakes")]
+    no_warnings: bool,
+    #[arg(
+        long,
+        alias = "dry-config",
+        help = "validate the configuration and exit without listening"
+    )]
+    check: bool,
+}
+
+struct AppState {
+    opt: Opt,
+    shutdown: watch::Sender<bool>,
+    /// set along with shutdown when something went wrong that we cannot
+    /// recover from, so we exit non-zero
+    failed: AtomicBool,
+    form: Bytes,
+    stdin: Option<stdin::Shared>,
+    sink: Option<sink::Sink>,
+    audit: Option<audit::Audit>,
+    bans: Option<ban::Bans>,
+    quota: Option<quota::Quota>,
+    names: Option<casefold::Names>,
+    journal: Option<ephemeral::Journal>,
+    sessions: Option<session::Sessions>,
+}
+
+async fn root(State(state): State<Arc<AppState>>) -> Html<Bytes> {
+    Html(state.form.clone())
 }
 
-async fn root() -> Html<&'static str> {
-    Html(include_str!("form.html"))
+async fn no_store<B>(mut res: Response<B>) -> Response<B> {
+    res.headers_mut()
+        .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+    res
+}
+
+/// turn plain text errors into {"status": 429, "error": "..."} for
+/// clients that ask for json, like the form's script
+async fn json_errors(req: axum::extract::Request, next: Next) -> Response {
+    let wants_json = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|a| a.to_str().ok())
+        .is_some_and(|a| a.contains("application/json"));
+    let res = next.run(req).await;
+    if !wants_json || !(res.status().is_client_error() || res.status().is_server_error()) {
+        return res;
+    }
+
+    let (mut parts, body) = res.into_parts();
+    let msg = axum::body::to_bytes(body, 65536).await.unwrap_or_default();
+    let json = serde_json::json!({
+        "status": parts.status.as_u16(),
+        "error": String::from_utf8_lossy(&msg).trim(),
+    });
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+    Response::from_parts(parts, json.to_string().into())
+}
+
+/// most things going wrong are down to what the client sent, running
+/// out of space or picking a taken name are not
+fn error_status<E: std::error::Error + 'static>(e: &E) -> StatusCode {
+    let e: &dyn std::error::Error = e;
+    match e.downcast_ref::<std::io::Error>().map(std::io::Error::kind) {
+        Some(std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded) => {
+            StatusCode::INSUFFICIENT_STORAGE
+        }
+        Some(std::io::ErrorKind::AlreadyExists) => StatusCode::CONFLICT,
+        _ => StatusCode::BAD_REQUEST,
+    }
 }
 
 macro_rules! unwrap_or_bad {
@@ -29,44 +416,1003 @@ macro_rules! unwrap_or_bad {
             Ok(v) => v,
             Err(e) => {
                 eprintln!("error {:?}", e);
-                return Err((StatusCode::BAD_REQUEST, e.to_string()));
+                return Err((error_status(&e), e.to_string()).into_response());
+            }
+        }
+    };
+}
+
+/// how long the names of uploads may get, leaving room for the .sha256
+/// sidecar of --write-checksums
+fn name_limit(opt: &Opt) -> usize {
+    match opt.write_checksums {
+        true => opt.max_name_len - ".sha256".len(),
+        false => opt.max_name_len,
+    }
+}
+
+/// shorten name to at most max bytes without splitting a character,
+/// keeping the extension if there is a sensible one
+fn truncate_name(name: &str, max: usize) -> String {
+    if name.len() <= max {
+        return name.to_string();
+    }
+
+    // treat a short second to last part as part of the extension, for .tar.gz and friends
+    let ext = name.rfind('.').map(|i| match name[..i].rfind('.') {
+        Some(j) if i - j <= 5 && name[j + 1..i].chars().all(|c| c.is_ascii_alphanumeric()) => j,
+        _ => i,
+    });
+    let (stem, ext) = match ext {
+        Some(i) if i > 0 && name.len() - i <= max / 2 => name.split_at(i),
+        _ => (name, ""),
+    };
+    let mut end = max - ext.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    format!("{}{}", &stem[..end], ext)
+}
+
+async fn file_name(state: &AppState, name: &str) -> Result<String, Response> {
+    let name = format!("quickshare_{}", name.replace('/', ""));
+    let name = match &state.opt.name_hook {
+        Some(cmd) => hook::rename(cmd, &name).await?,
+        None => name,
+    };
+    let name = truncate_name(&name, name_limit(&state.opt));
+
+    let dir = route::dir_for(
+        &state.opt.route_ext,
+        state.opt.route_default.as_deref(),
+        &name,
+    );
+    let Some(dir) = dir else {
+        return Ok(name);
+    };
+    if let Some(journal) = &state.journal {
+        for part in route::parts(dir) {
+            if std::fs::symlink_metadata(part).is_err() {
+                journal.record_dir(part);
+            }
+        }
+    }
+    if let Err(e) = tokio::fs::create_dir_all(dir).await {
+        eprintln!("error creating {}: {}", dir, e);
+        if let Some(file) = route::file_in_the_way(dir) {
+            return Err((
+                StatusCode::CONFLICT,
+                format!("path component is a file: {}", file),
+            )
+                .into_response());
+        }
+        return Err((error_status(&e), e.to_string()).into_response());
+    }
+    Ok(format!("{}/{}", dir, name))
+}
+
+/// where an upload's bytes end up
+enum Output {
+    File(File),
+    /// a named pipe, whose writes block until someone reads
+    Fifo(File),
+    Stdout {
+        /// whether anything made it out yet, which cannot be taken back
+        written: bool,
+    },
+    /// written under a hidden name until it has been checked
+    Staged {
+        file: File,
+        tmp: String,
+        /// whether commit may replace a file that is already there
+        replace: bool,
+    },
+}
+
+#[cfg(unix)]
+fn is_fifo(state: &AppState, name: &str) -> bool {
+    use std::os::unix::fs::FileTypeExt;
+    state.opt.fifo_mode && std::fs::metadata(name).is_ok_and(|m| m.file_type().is_fifo())
+}
+
+#[cfg(not(unix))]
+fn is_fifo(_state: &AppState, _name: &str) -> bool {
+    false
+}
+
+impl Output {
+    fn create(state: &AppState, name: &str) -> std::io::Result<Self> {
+        if state.sink.is_some() {
+            return Ok(Self::Stdout { written: false });
+        }
+
+        if is_fifo(state, name) {
+            // opening blocks until there is a reader on the other end
+            let fifo =
+                tokio::task::block_in_place(|| std::fs::OpenOptions::new().write(true).open(name))?;
+            return Ok(Self::Fifo(fifo));
+        }
+
+        if let Some(names) = &state.names {
+            names.claim(name)?;
+        }
+        let file = File::create_new(name).inspect_err(|e| {
+            // it is there after all, just not in the index
+            if let Some(names) = &state.names {
+                if e.kind() != std::io::ErrorKind::AlreadyExists {
+                    names.release(name);
+                }
+            }
+        })?;
+        if let Some(journal) = &state.journal {
+            journal.record(name);
+        }
+        Ok(Self::File(file))
+    }
+
+    /// like create, but nothing shows up under name until commit
+    fn staged(state: &AppState, name: &str, replace: bool) -> std::io::Result<Self> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let unverifiable = || {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "uploads can only be verified when saved as files",
+            )
+        };
+        if state.sink.is_some() {
+            return Err(unverifiable());
+        }
+        #[cfg(unix)]
+        if state.opt.fifo_mode {
+            return Err(unverifiable());
+        }
+        let exists = std::fs::symlink_metadata(name).is_ok();
+        if exists && !replace {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "file exists",
+            ));
+        }
+        if let Some(names) = &state.names {
+            // replacing the very same file is fine, a case variant is not
+            if let Err(e) = names.claim(name) {
+                if !exists {
+                    return Err(e);
+                }
+            }
+        }
+
+        let tmp = format!(
+            ".quickshare_partial_{}_{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        );
+        let file = File::create_new(&tmp).inspect_err(|_| {
+            if let Some(names) = &state.names {
+                if !exists {
+                    names.release(name);
+                }
+            }
+        })?;
+        if let Some(journal) = &state.journal {
+            journal.record(&tmp);
+        }
+        Ok(Self::Staged { file, tmp, replace })
+    }
+
+    /// create or stage name as the collision policy says, along with the
+    /// name it actually ended up under
+    fn open(
+        state: &AppState,
+        name: &str,
+        conflict: conflict::Policy,
+        stage: bool,
+    ) -> std::io::Result<(Self, String)> {
+        let create = |name: &str| match stage {
+            true => Self::staged(state, name, false),
+            false => Self::create(state, name),
+        };
+        match conflict {
+            conflict::Policy::Error => create(name).map(|o| (o, name.to_string())),
+            // nothing to replace when it is not going into a file anyway
+            conflict::Policy::Overwrite if state.sink.is_some() || is_fifo(state, name) => {
+                create(name).map(|o| (o, name.to_string()))
+            }
+            conflict::Policy::Overwrite => {
+                Self::staged(state, name, true).map(|o| (o, name.to_string()))
+            }
+            conflict::Policy::Rename => {
+                for candidate in conflict::candidates(name, name_limit(&state.opt)).take(1000) {
+                    match create(&candidate) {
+                        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                        res => return res.map(|o| (o, candidate)),
+                    }
+                }
+                Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    "every variant of this name is taken",
+                ))
+            }
+        }
+    }
+
+    /// move a staged file to its real name, or under --on-conflict
+    /// rename the next free one if it got taken in the meantime
+    fn commit(
+        self,
+        state: &AppState,
+        name: &str,
+        conflict: conflict::Policy,
+    ) -> std::io::Result<(Self, String)> {
+        let Self::Staged { file, tmp, replace } = self else {
+            return Ok((self, name.to_string()));
+        };
+        // a file that was there before this run is not ours to have
+        // --ephemeral delete, even once replaced
+        let replaced = replace && std::fs::symlink_metadata(name).is_ok();
+
+        let mut candidates = conflict::candidates(name, name_limit(&state.opt)).take(1000);
+        let moved = loop {
+            let Some(candidate) = candidates.next() else {
+                break Err(std::io::Error::new(
+                    std::io::ErrorKind::AlreadyExists,
+                    "every variant of this name is taken",
+                ));
+            };
+            // name itself was claimed when staging
+            if let (Some(names), true) = (&state.names, candidate != name) {
+                if names.claim(&candidate).is_err() {
+                    continue;
+                }
+            }
+            // unless asked to replace, link rather than rename, as that
+            // never replaces a file that showed up in the meantime
+            let res = match replace {
+                true => std::fs::rename(&tmp, &candidate),
+                false => std::fs::hard_link(&tmp, &candidate),
+            };
+            match res {
+                // whoever took it keeps its claim
+                Err(e)
+                    if e.kind() == std::io::ErrorKind::AlreadyExists
+                        && conflict == conflict::Policy::Rename => {}
+                Err(e) => {
+                    if let (Some(names), true) = (&state.names, candidate != name) {
+                        names.release(&candidate);
+                    }
+                    break Err(e);
+                }
+                Ok(()) => break Ok(candidate),
+            }
+        };
+        _ = std::fs::remove_file(&tmp);
+
+        let name = match moved {
+            Ok(name) => name,
+            Err(e) => {
+                if let Some(names) = &state.names {
+                    if std::fs::symlink_metadata(name).is_err() {
+                        names.release(name);
+                    }
+                }
+                return Err(e);
+            }
+        };
+        if let Some(journal) = &state.journal {
+            if !replaced {
+                journal.record(&name);
+            }
+        }
+        Ok((Self::File(file), name))
+    }
+
+    /// write a sha256sum style sidecar for a file that just finished
+    ///
+    /// the upload itself is stored by now, so failing here is only logged
+    fn write_checksum(
+        &self,
+        state: &AppState,
+        name: &str,
+        hash: &[u8],
+        conflict: conflict::Policy,
+    ) {
+        if !matches!(self, Self::File(_)) {
+            return;
+        }
+        let hex: String = hash.iter().map(|b| format!("{:02x}", b)).collect();
+        let sidecar = format!("{}.sha256", name);
+        let res = Self::open(state, &sidecar, conflict, false).and_then(|(mut out, sidecar)| {
+            // the sidecar sits next to its file, so sha256sum -c works from there
+            let base = name.rsplit('/').next().unwrap_or(name);
+            out.write_all(format!("{}  {}\n", hex, base).as_bytes())?;
+            out.commit(state, &sidecar, conflict)
+        });
+        if let Err(e) = res {
+            eprintln!("error writing {}: {}", sidecar, e);
+        }
+    }
+
+    /// check what was written against --validate, streams cannot be read
+    /// back so they always pass
+    fn validate(&self, state: &AppState, name: &str) -> Result<(), (StatusCode, String)> {
+        let Some(kind) = state.opt.validate else {
+            return Ok(());
+        };
+        let path = match self {
+            Self::File(_) => name,
+            Self::Staged { tmp, .. } => tmp,
+            Self::Fifo(_) | Self::Stdout { .. } => return Ok(()),
+        };
+        tokio::task::block_in_place(|| validate::check(kind, path))
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> std::io::Result<()> {
+        match self {
+            Self::File(file) | Self::Staged { file, .. } => file.write_all(buf),
+            Self::Fifo(fifo) => tokio::task::block_in_place(|| fifo.write_all(buf)),
+            Self::Stdout { written } => {
+                *written |= !buf.is_empty();
+                std::io::stdout().write_all(buf)
+            }
+        }
+    }
+
+    /// get rid of what was written so far, as far as that is possible
+    fn discard(self, state: &AppState, name: &str) {
+        match self {
+            Self::File(_) => _ = std::fs::remove_file(name),
+            Self::Staged { tmp, .. } => _ = std::fs::remove_file(tmp),
+            Self::Stdout { written: true } => {
+                if let Some(sink) = &state.sink {
+                    sink.spoil(state);
+                }
+                return;
+            }
+            Self::Fifo(_) | Self::Stdout { written: false } => return,
+        }
+        // an overwrite that failed leaves the old file, still taken
+        if let Some(names) = &state.names {
+            if std::fs::symlink_metadata(name).is_err() {
+                names.release(name);
+            }
+        }
+    }
+}
+
+fn budget<'a>(
+    state: &'a AppState,
+    remote: SocketAddr,
+    headers: &HeaderMap,
+) -> Result<quota::Budget<'a>, quota::Exceeded> {
+    let Some(quota) = &state.quota else {
+        return Ok(quota::Budget::unlimited(remote.ip()));
+    };
+    let length = headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|l| l.to_str().ok())
+        .and_then(|l| l.parse().ok());
+    quota.budget(remote.ip(), length)
+}
+
+/// --require-host, for setups that route on the Host header
+fn require_host(state: &AppState, headers: &HeaderMap) -> Result<(), (StatusCode, &'static str)> {
+    if state.opt.require_host && !headers.contains_key(header::HOST) {
+        return Err((StatusCode::BAD_REQUEST, "missing host header"));
+    }
+    Ok(())
+}
+
+/// --allowed-host, so a rebound dns name pointing at us gets nowhere
+async fn allowed_host(
+    State(state): State<Arc<AppState>>,
+    req: axum::extract::Request,
+    next: Next,
+) -> Response {
+    let host = req
+        .headers()
+        .get(header::HOST)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.parse::<axum::http::uri::Authority>().ok());
+    // ipv6 hosts come with their brackets still on
+    let allowed = host.is_some_and(|host| {
+        let host = host.host().trim_start_matches('[').trim_end_matches(']');
+        state
+            .opt
+            .allowed_host
+            .iter()
+            .any(|a| a.eq_ignore_ascii_case(host))
+    });
+    if !allowed {
+        return (StatusCode::MISDIRECTED_REQUEST, "unexpected host").into_response();
+    }
+    next.run(req).await
+}
+
+/// the sha256 a client wants its upload checked against before it is kept
+fn expected_hash(headers: &HeaderMap) -> Result<Option<[u8; 32]>, (StatusCode, &'static str)> {
+    let Some(hex) = headers.get("x-expected-sha256") else {
+        return Ok(None);
+    };
+    let bad = (
+        StatusCode::BAD_REQUEST,
+        "x-expected-sha256 must be 64 hex digits",
+    );
+    let hex = hex.as_bytes();
+    if hex.len() != 64 {
+        return Err(bad);
+    }
+    let mut hash = [0; 32];
+    for (byte, pair) in hash.iter_mut().zip(hex.chunks(2)) {
+        let pair = std::str::from_utf8(pair).map_err(|_| bad)?;
+        *byte = u8::from_str_radix(pair, 16).map_err(|_| bad)?;
+    }
+    Ok(Some(hash))
+}
+
+/// store a multipart file field, under the name the client gave it
+async fn save_field(
+    state: &AppState,
+    field: Field<'_>,
+    budget: &mut quota::Budget<'_>,
+    expected: Option<[u8; 32]>,
+    conflict: conflict::Policy,
+) -> Result<String, Response> {
+    let original = match field.file_name() {
+        Some(name) if !name.is_empty() => name.to_string(),
+        _ => unnamed::name(state.opt.unnamed_policy).map_err(IntoResponse::into_response)?,
+    };
+    save(state, original, field, budget, expected, conflict).await
+}
+
+/// store an upload called original by the client, from wherever its
+/// bytes come from
+async fn save<E: std::error::Error + 'static>(
+    state: &AppState,
+    original: String,
+    body: impl Stream<Item = Result<Bytes, E>>,
+    budget: &mut quota::Budget<'_>,
+    expected: Option<[u8; 32]>,
+    conflict: conflict::Policy,
+) -> Result<String, Response> {
+    let name = file_name(state, &original).await?;
+
+    let start = budget.used;
+    let res = write_body(state, &name, body, budget, expected, conflict).await;
+    audit::log(
+        state,
+        audit::Event {
+            event: "upload",
+            ip: budget.ip(),
+            target: res.as_deref().unwrap_or(&name),
+            bytes: Some(budget.used - start),
+            outcome: res
+                .as_ref()
+                .map_or_else(|e| e.status(), |_| StatusCode::OK)
+                .as_u16(),
+        },
+    );
+    let name = res?;
+
+    finished(state, &name, budget.used - start, budget.ip(), &original);
+    Ok(name)
+}
+
+/// everything that follows an upload being stored under name, however it
+/// arrived
+fn finished(state: &AppState, name: &str, bytes: u64, ip: IpAddr, original: &str) {
+    eprintln!("received {}", name);
+    if state.opt.notify {
+        notify::arrived(name, bytes, ip);
+    }
+    if let Some(url) = &state.opt.mirror {
+        mirror::spawn(url.clone(), name.to_string(), original.to_string());
+    }
+}
+
+async fn write_body<E: std::error::Error + 'static>(
+    state: &AppState,
+    name: &str,
+    body: impl Stream<Item = Result<Bytes, E>>,
+    budget: &mut quota::Budget<'_>,
+    expected: Option<[u8; 32]>,
+    conflict: conflict::Policy,
+) -> Result<String, Response> {
+    let start = budget.used;
+    let (mut file, name) = unwrap_or_bad!(Output::open(state, name, conflict, expected.is_some()));
+    let mut hasher = (state.opt.write_checksums || expected.is_some()).then(Sha256::new);
+    let mut body = std::pin::pin!(body);
+    loop {
+        let chunk = match body.next().await {
+            Some(Ok(chunk)) => chunk,
+            None => break,
+            Some(Err(e)) => {
+                // a timed out or dropped upload is not worth keeping half of
+                budget.used = start;
+                file.discard(state, &name);
+                eprintln!("error {:?}", e);
+                return Err((error_status(&e), e.to_string()).into_response());
             }
+        };
+        if let Err(e) = budget.take(chunk.len() as u64) {
+            budget.used = start;
+            file.discard(state, &name);
+            return Err(e.into());
+        }
+        if let Err(e) = file.write_all(&chunk) {
+            budget.used = start;
+            file.discard(state, &name);
+            eprintln!("error {:?}", e);
+            return Err((error_status(&e), e.to_string()).into_response());
+        }
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&chunk);
+        }
+    }
+    let hash = hasher.map(Sha256::finalize);
+    if let (Some(expected), Some(hash)) = (expected, &hash) {
+        if expected != hash.as_slice() {
+            budget.used = start;
+            file.discard(state, &name);
+            return Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "upload does not match x-expected-sha256",
+            )
+                .into_response());
+        }
+    }
+    if let Err(e) = file.validate(state, &name) {
+        budget.used = start;
+        file.discard(state, &name);
+        return Err(e.into_response());
+    }
+    let (file, name) = match file.commit(state, &name, conflict) {
+        Ok(committed) => committed,
+        Err(e) => {
+            budget.used = start;
+            eprintln!("error {:?}", e);
+            return Err((error_status(&e), e.to_string()).into_response());
         }
     };
+    if let (true, Some(hash)) = (state.opt.write_checksums, &hash) {
+        file.write_checksum(state, &name, hash, conflict);
+    }
+
+    Ok(name)
+}
+
+async fn upload(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    multipart: Multipart,
+) -> Result<&'static str, Response> {
+    require_host(&state, &headers).map_err(IntoResponse::into_response)?;
+    let claim = match &state.sink {
+        Some(sink) => Some(sink.claim().map_err(IntoResponse::into_response)?),
+        None => None,
+    };
+    let expected = expected_hash(&headers).map_err(IntoResponse::into_response)?;
+    let conflict = conflict::for_request(state.opt.on_conflict, &headers);
+    let mut budget = budget(&state, remote, &headers)?;
+    let received = upload_inner(&state, multipart, &mut budget, expected, conflict).await?;
+    // with no file there is nothing on stdout to finish, keep waiting
+    if let (true, Some(claim)) = (received, claim) {
+        unwrap_or_bad!(claim.finish(&state));
+    }
+    match received {
+        true => Ok("uploaded~"),
+        false => Ok("you did not send a file? less work for me i guess"),
+    }
 }
 
-async fn upload(mut multipart: Multipart) -> Result<&'static str, (StatusCode, String)> {
-    while let Some(mut field) = unwrap_or_bad!(multipart.next_field().await) {
+async fn upload_inner(
+    state: &AppState,
+    mut multipart: Multipart,
+    budget: &mut quota::Budget<'_>,
+    expected: Option<[u8; 32]>,
+    conflict: conflict::Policy,
+) -> Result<bool, Response> {
+    while let Some(field) = unwrap_or_bad!(multipart.next_field().await) {
         if Some("file") != field.name() {
             continue;
         }
 
-        let name = format!(
-            "quickshare_{}",
-            field.file_name().unwrap_or("untitled").replace('/', "")
-        );
+        save_field(state, field, budget, expected, conflict).await?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+async fn share(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    multipart: Multipart,
+) -> Result<Html<String>, Response> {
+    require_host(&state, &headers).map_err(IntoResponse::into_response)?;
+    let claim = match &state.sink {
+        Some(sink) => Some(sink.claim().map_err(IntoResponse::into_response)?),
+        None => None,
+    };
+    let conflict = conflict::for_request(state.opt.on_conflict, &headers);
+    let mut budget = budget(&state, remote, &headers)?;
+    let received = share_inner(&state, multipart, &mut budget, conflict).await?;
+    if let (true, Some(claim)) = (received, claim) {
+        unwrap_or_bad!(claim.finish(&state));
+    }
+    Ok(pwa::shared(&state))
+}
+
+/// target of the pwa share sheet, files are stored like uploads and
+/// anything else shared becomes a text file, answers whether anything was
+/// stored at all
+async fn share_inner(
+    state: &AppState,
+    mut multipart: Multipart,
+    budget: &mut quota::Budget<'_>,
+    conflict: conflict::Policy,
+) -> Result<bool, Response> {
+    let mut files = 0;
+    let mut title = None;
+    let mut text = vec![];
 
-        let mut file = unwrap_or_bad!(File::create_new(&name));
-        while let Some(chunk) = unwrap_or_bad!(field.chunk().await) {
-            unwrap_or_bad!(file.write_all(&chunk));
+    while let Some(field) = unwrap_or_bad!(multipart.next_field().await) {
+        match field.name() {
+            // browsers send an empty file field when only text is shared
+            Some("file") if field.file_name().is_some_and(|n| !n.is_empty()) => {
+                save_field(state, field, budget, None, conflict).await?;
+                files += 1;
+            }
+            Some("title") => title = Some(unwrap_or_bad!(field.text().await)),
+            Some("text" | "url") => text.push(unwrap_or_bad!(field.text().await)),
+            _ => (),
         }
+    }
 
-        eprintln!("received {}", name);
-        return Ok("uploaded~");
+    text.retain(|t| !t.is_empty());
+    if files == 0 && !text.is_empty() {
+        let title = title.filter(|t| !t.is_empty());
+        let original = format!("{}.txt", title.as_deref().unwrap_or("shared"));
+        let text = Bytes::from(text.join("\n"));
+        let body = futures_util::stream::iter([Ok::<_, std::io::Error>(text)]);
+        save(state, original, body, budget, None, conflict).await?;
+        files += 1;
     }
 
-    Ok("you did not send a file? less work for me i guess")
+    Ok(files > 0)
 }
 
 #[tokio::main]
 async fn main() {
     let opt = Opt::parse();
-    let app = Router::new()
-        .route("/", get(root))
-        .route("/", post(upload))
-        .layer(DefaultBodyLimit::max(opt.limit * 1048576));
-
-    let listen = TcpListener::bind(&opt.bindhost).await.unwrap();
-    eprintln!("listening on {}", listen.local_addr().unwrap());
-    axum::serve(listen, app.into_make_service()).await.unwrap();
+    let addrs = check::validate(&opt).unwrap_or_else(|errors| {
+        for e in errors {
+            eprintln!("error {}", e);
+        }
+        std::process::exit(1);
+    });
+    if !opt.no_warnings {
+        check::warnings(&opt);
+    }
+    if opt.check {
+        check::summary(&opt, &addrs);
+        return;
+    }
+    if opt.ephemeral_cleanup {
+        match ephemeral::cleanup(ephemeral::JOURNAL.as_ref()) {
+            Ok(removed) => eprintln!("removed {} files", removed),
+            Err(e) => {
+                eprintln!("error cleaning up {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // one left by an earlier run would look like we are ready already
+    if let Some(path) = &opt.port_file {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                eprintln!("error removing {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+            _ => (),
+        }
+    }
+
+    let stdin = match &opt.stdin {
+        Some(name) => Some(
+            stdin::Shared::new(
+                name,
+                opt.content_type.as_deref(),
+                &opt.mime,
+                opt.count,
+                opt.stream,
+                opt.limit as u64 * 1048576,
+            )
+            .await
+            .unwrap_or_else(|e| {
+                eprintln!("error reading stdin: {}", e);
+                std::process::exit(1);
+            }),
+        ),
+        None => None,
+    };
+
+    let form = include_str!("form.html");
+    let state = Arc::new(AppState {
+        shutdown: watch::Sender::new(false),
+        failed: AtomicBool::new(false),
+        stdin,
+        sink: opt.stdout.then(sink::Sink::new),
+        form: if opt.pwa {
+            pwa::form(form).into()
+        } else {
+            form.into()
+        },
+        audit: opt.audit_log.clone().map(|path| {
+            audit::Audit::new(path).unwrap_or_else(|e| {
+                eprintln!("error opening audit log {}", e);
+                std::process::exit(1);
+            })
+        }),
+        bans: opt.ban_after.map(|after| {
+            ban::Bans::new(after, Duration::from_secs(opt.ban_time), opt.ban_loopback)
+        }),
+        quota: opt.ip_quota.map(|spec| {
+            quota::Quota::new(spec, opt.quota_exempt.clone(), opt.quota_file.clone())
+                .unwrap_or_else(|e| {
+                    eprintln!("error {}", e);
+                    std::process::exit(1);
+                })
+        }),
+        names: opt.case_insensitive.then(|| {
+            let dirs = opt.route_ext.iter().map(|r| r.dir.as_str());
+            casefold::Names::new(dirs.chain(opt.route_default.as_deref())).unwrap_or_else(|e| {
+                eprintln!("error reading upload directory {}", e);
+                std::process::exit(1);
+            })
+        }),
+        sessions: opt
+            .resumable
+            .then(|| session::Sessions::new(opt.session_ttl)),
+        journal: opt.ephemeral.then(|| {
+            ephemeral::Journal::new(ephemeral::JOURNAL).unwrap_or_else(|e| {
+                eprintln!("error starting {} {}", ephemeral::JOURNAL, e);
+                std::process::exit(1);
+            })
+        }),
+        opt,
+    });
+    let opt = &state.opt;
+
+    let mut app = Router::new().route("/capabilities", get(capabilities::capabilities));
+    if let Some(name) = &opt.stdin {
+        let mut download = get(stdin::download);
+        if opt.compress_downloads {
+            download = download.layer(compress::layer(&opt.compress_types, opt.compress_min));
+        }
+        app = app.route(&format!("/{}", name), download);
+    } else {
+        app = app.route(&opt.form_path, get(root)).route(
+            &opt.form_path,
+            post(upload)
+                .layer(map_response(no_store))
+                .layer(from_fn(json_errors)),
+        );
+    }
+    if let Some(page) = &opt.root_page {
+        app = app.route_service(
+            "/",
+            ServeFile::new_with_mime(page, &mimes::guess(&opt.mime, page)),
+        );
+    }
+    if opt.resumable {
+        app = app
+            .route("/session", post(session::start))
+            .route("/session/:id", get(session::offset).patch(session::append))
+            .route("/session/:id/complete", post(session::complete));
+    }
+    if opt.pwa && opt.stdin.is_none() {
+        app = app
+            .route("/manifest.webmanifest", get(pwa::manifest))
+            .route("/sw.js", get(pwa::service_worker))
+            .route("/icon.svg", get(pwa::icon))
+            .route("/share", post(share).layer(map_response(no_store)));
+    }
+    let mut app = app
+        .layer(DefaultBodyLimit::max(opt.limit * 1048576))
+        .with_state(state.clone());
+    app = app.layer(RequestBodyTimeoutLayer::new(Duration::from_secs(
+        opt.body_timeout,
+    )));
+    if let Some(secs) = opt.request_timeout {
+        app = app.layer(TimeoutLayer::with_status_code(
+            StatusCode::REQUEST_TIMEOUT,
+            Duration::from_secs(secs),
+        ));
+    }
+    if opt.no_cache {
+        app = app.layer(map_response(no_store));
+    }
+    if !opt.allowed_host.is_empty() {
+        app = app.layer(from_fn_with_state(state.clone(), allowed_host));
+    }
+    if state.bans.is_some() {
+        app = app.layer(from_fn_with_state(state.clone(), ban::guard));
+    }
+    if state.audit.is_some() {
+        app = app.layer(from_fn_with_state(state.clone(), audit::middleware));
+    }
+
+    let mut servers = JoinSet::new();
+    let mut ports = String::new();
+    for addr in addrs {
+        let listen = listen::bind(addr, opt).unwrap_or_else(|e| {
+            eprintln!("error binding {}: {}", addr, e);
+            std::process::exit(1);
+        });
+        let local = listen.local_addr().unwrap();
+        eprintln!("listening on {}", local);
+        ports.push_str(&format!("{}\n", local.port()));
+        if let Some(name) = &opt.stdin {
+            eprintln!("sharing stdin at http://{}/{}", local, name);
+        }
+        servers.spawn(serve::serve(listen, app.clone(), state.clone()));
+    }
+    if let Some(path) = &opt.port_file {
+        // written once every listener is up, and renamed into place so
+        // its existence means ready
+        let tmp = path.with_extension("tmp");
+        if let Err(e) = std::fs::write(&tmp, ports).and_then(|_| std::fs::rename(&tmp, path)) {
+            eprintln!("error writing {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(path) = state.stdin.as_ref().and_then(|s| s.path.clone()) {
+        tokio::spawn(async move {
+            _ = tokio::signal::ctrl_c().await;
+            _ = std::fs::remove_file(&path);
+            std::process::exit(130);
+        });
+    }
+
+    if let Some(quota) = &state.quota {
+        let state = state.clone();
+        if quota.persisting() {
+            tokio::spawn(async move { state.quota.as_ref().unwrap().persist().await });
+        }
+    }
+
+    if let Some(sessions) = &state.sessions {
+        let state = state.clone();
+        let mut tick = tokio::time::interval(Duration::from_secs(60).min(sessions.ttl()));
+        tokio::spawn(async move {
+            loop {
+                tick.tick().await;
+                state.sessions.as_ref().unwrap().expire(&state);
+            }
+        });
+    }
+
+    if state.journal.is_some() {
+        let state = state.clone();
+        tokio::spawn(async move {
+            _ = tokio::signal::ctrl_c().await;
+            eprintln!("finishing uploads, ctrl-c again to stop now");
+            state.shutdown.send_replace(true);
+            _ = tokio::signal::ctrl_c().await;
+            clean_up_ephemeral(&state);
+            std::process::exit(130);
+        });
+    }
+
+    #[cfg(unix)]
+    if state.audit.is_some() {
+        use tokio::signal::unix::{signal, SignalKind};
+        let state = state.clone();
+        let mut hangup = signal(SignalKind::hangup()).unwrap();
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                state.audit.as_ref().unwrap().reopen();
+            }
+        });
+    }
+
+    while let Some(res) = servers.join_next().await {
+        res.unwrap();
+    }
+
+    if let Some(path) = state.stdin.as_ref().and_then(|s| s.path.as_ref()) {
+        _ = std::fs::remove_file(path);
+    }
+    if let Some(sessions) = &state.sessions {
+        sessions.close(&state);
+    }
+    if let Some(quota) = &state.quota {
+        quota.save();
+    }
+    clean_up_ephemeral(&state);
+    if state.failed.load(Ordering::SeqCst) {
+        std::process::exit(1);
+    }
+}
+
+fn clean_up_ephemeral(state: &AppState) {
+    if let Some(journal) = &state.journal {
+        match journal.finish() {
+            Ok(removed) => eprintln!("removed {} files", removed),
+            Err(e) => eprintln!("error cleaning up {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::truncate_name;
+
+    #[test]
+    fn short_names_are_kept() {
+        for len in [254, 255] {
+            let name = format!("{}.txt", "a".repeat(len - 4));
+            assert_eq!(truncate_name(&name, 255), name);
+        }
+        // 85 three byte characters
+        let name = "語".repeat(85);
+        assert_eq!(truncate_name(&name, 255), name);
+    }
+
+    #[test]
+    fn long_names_keep_their_extension() {
+        let name = format!("{}.txt", "a".repeat(252));
+        let short = truncate_name(&name, 255);
+        assert_eq!(short.len(), 255);
+        assert!(short.ends_with("a.txt"));
+    }
+
+    #[test]
+    fn multibyte_stems_are_not_split() {
+        // 126 two byte characters and .txt make 256 bytes, cutting to 255
+        // would land in the middle of one
+        let name = format!("{}.txt", "é".repeat(126));
+        let short = truncate_name(&name, 255);
+        assert_eq!(short, format!("{}.txt", "é".repeat(125)));
+        assert_eq!(short.len(), 254);
+
+        let name = format!("{}.txt", "語".repeat(84));
+        let short = truncate_name(&name, 255);
+        assert_eq!(short, format!("{}.txt", "語".repeat(83)));
+    }
+
+    #[test]
+    fn multibyte_extensions_are_kept_whole() {
+        let name = format!("{}.日本", "a".repeat(250));
+        let short = truncate_name(&name, 255);
+        assert_eq!(short, format!("{}.日本", "a".repeat(248)));
+        assert_eq!(short.len(), 255);
+    }
+
+    #[test]
+    fn double_extensions_are_kept() {
+        let name = format!("{}.tar.gz", "a".repeat(300));
+        let short = truncate_name(&name, 255);
+        assert_eq!(short.len(), 255);
+        assert!(short.ends_with("a.tar.gz"));
+
+        // but not when the second to last part is just more name
+        let name = format!("{}.version_two.gz", "a".repeat(300));
+        assert!(truncate_name(&name, 255).ends_with("a.gz"));
+    }
+
+    #[test]
+    fn absurd_extensions_are_cut_like_the_rest() {
+        let name = format!("a.{}", "é".repeat(200));
+        let short = truncate_name(&name, 255);
+        assert!(short.len() <= 255 && short.len() >= 254);
+        assert!(name.starts_with(&short));
+    }
 }
@@ -0,0 +1,45 @@
+use std::{path::Path, str::FromStr};
+
+use mime_guess::Mime;
+
+/// `--mime json5=application/json5`, the content type to serve files with
+/// an extension as, instead of guessing one
+#[derive(Debug, Clone)]
+pub struct Override {
+    pub ext: String,
+    pub mime: Mime,
+}
+
+impl FromStr for Override {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (ext, mime) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected EXT=TYPE, got {:?}", s))?;
+        let ext = ext.trim().trim_start_matches('.').to_lowercase();
+        if ext.is_empty() {
+            return Err(format!("no extension in {:?}", s));
+        }
+        let mime = mime
+            .trim()
+            .parse()
+            .map_err(|_| format!("{:?} is not a valid content type", mime))?;
+        Ok(Self { ext, mime })
+    }
+}
+
+/// the content type for path, from --mime when it has a say
+pub fn guess(overrides: &[Override], path: impl AsRef<Path>) -> Mime {
+    let path = path.as_ref();
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase);
+    overrides
+        .iter()
+        .rev()
+        .find(|o| Some(&o.ext) == ext.as_ref())
+        .map(|o| o.mime.clone())
+        .unwrap_or_else(|| mime_guess::from_path(path).first_or_octet_stream())
+}
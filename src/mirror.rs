@@ -0,0 +1,104 @@
+use std::{io, time::Duration};
+
+use axum::{
+    body::{Body, Bytes},
+    http::{header, Request, Uri},
+};
+use futures_util::StreamExt;
+use hyper::client::conn::http1;
+use hyper_util::rt::TokioIo;
+use tokio::{fs::File, io::AsyncReadExt, net::TcpStream};
+
+/// how long the peer gets to take the whole upload before we give up
+const TIMEOUT: Duration = Duration::from_secs(3600);
+
+/// `--mirror`, must be plain http since we do not speak tls
+pub fn parse_url(s: &str) -> Result<Uri, String> {
+    let uri: Uri = s
+        .parse()
+        .map_err(|e| format!("invalid url {:?}: {}", s, e))?;
+    if uri.scheme_str() != Some("http") || uri.authority().is_none() {
+        return Err(format!("{:?} must be an http:// url", s));
+    }
+    Ok(uri)
+}
+
+/// send a copy of a finished upload to the peer in the background,
+/// posting it to its form like a browser would
+///
+/// name is where it is stored here, original what the client called it,
+/// so the peer ends up picking the same name
+pub fn spawn(url: Uri, name: String, original: String) {
+    tokio::spawn(async move {
+        let res = tokio::time::timeout(TIMEOUT, send(&url, &name, &original)).await;
+        match res {
+            Ok(Ok(())) => eprintln!("mirrored {} to {}", name, url),
+            Ok(Err(e)) => eprintln!("error mirroring {} to {}: {}", name, url, e),
+            Err(_) => eprintln!("error mirroring {} to {}: timed out", name, url),
+        }
+    });
+}
+
+async fn send(url: &Uri, name: &str, original: &str) -> io::Result<()> {
+    let file = File::open(name).await?;
+    let size = file.metadata().await?.len();
+
+    // random, so no file can contain it and end its part early
+    let mut id = [0; 16];
+    getrandom::fill(&mut id).expect("no randomness for multipart boundaries");
+    let boundary: String = std::iter::once("quickshare-".to_string())
+        .chain(id.iter().map(|b| format!("{:02x}", b)))
+        .collect();
+    let original: String = original
+        .chars()
+        .filter(|c| !matches!(c, '"' | '\r' | '\n'))
+        .collect();
+    let head = format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+        boundary, original
+    );
+    let tail = format!("\r\n--{}--\r\n", boundary);
+    let length = head.len() as u64 + size + tail.len() as u64;
+
+    let contents = futures_util::stream::unfold(file, |mut file| async {
+        let mut buf = vec![0; 65536];
+        match file.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok::<_, io::Error>(Bytes::from(buf)), file))
+            }
+            Err(e) => Some((Err(e), file)),
+        }
+    });
+    let body = futures_util::stream::iter([Ok(Bytes::from(head))])
+        .chain(contents)
+        .chain(futures_util::stream::iter([Ok(Bytes::from(tail))]));
+
+    let authority = url.authority().unwrap();
+    // ipv6 hosts come with their brackets still on
+    let host = authority
+        .host()
+        .trim_start_matches('[')
+        .trim_end_matches(']');
+    let stream = TcpStream::connect((host, authority.port_u16().unwrap_or(80))).await?;
+    let (mut sender, conn) = http1::handshake(TokioIo::new(stream))
+        .await
+        .map_err(io::Error::other)?;
+    tokio::spawn(conn);
+
+    let req = Request::post(url.path())
+        .header(header::HOST, authority.as_str())
+        .header(
+            header::CONTENT_TYPE,
+            format!("multipart/form-data; boundary={}", boundary),
+        )
+        .header(header::CONTENT_LENGTH, length)
+        .body(Body::from_stream(body))
+        .map_err(io::Error::other)?;
+    let res = sender.send_request(req).await.map_err(io::Error::other)?;
+    if !res.status().is_success() {
+        return Err(io::Error::other(format!("peer answered {}", res.status())));
+    }
+    Ok(())
+}
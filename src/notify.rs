@@ -0,0 +1,25 @@
+use std::net::IpAddr;
+
+/// let whoever is at the desk know a file arrived, without ever holding
+/// up or failing the upload itself
+pub fn arrived(name: &str, bytes: u64, ip: IpAddr) {
+    let body = format!("{} ({} bytes) from {}", name, bytes, ip);
+
+    #[cfg(feature = "notify")]
+    tokio::task::spawn_blocking(move || {
+        let shown = notify_rust::Notification::new()
+            .appname("quickshare")
+            .summary("file received")
+            .body(&body)
+            .show();
+        if shown.is_err() {
+            bell(&body);
+        }
+    });
+    #[cfg(not(feature = "notify"))]
+    bell(&body);
+}
+
+fn bell(body: &str) {
+    eprintln!("\x07\x1b[1;7m received {} \x1b[0m", body);
+}
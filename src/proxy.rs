@@ -0,0 +1,250 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+};
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+/// longest a v1 header is allowed to be, including the CRLF
+const V1_MAX: usize = 107;
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// read the PROXY protocol header a load balancer puts in front of each
+/// connection, returning the address of the client it is for
+///
+/// remote is where the connection really came from, and is used for
+/// headers that do not carry an address, like health checks
+pub async fn read_header<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    remote: SocketAddr,
+) -> io::Result<SocketAddr> {
+    // the shortest valid v1 header is "PROXY UNKNOWN\r\n" so this never
+    // reads past the end of a header
+    let mut start = [0; 12];
+    stream.read_exact(&mut start).await?;
+
+    if &start == V2_SIGNATURE {
+        return read_v2(stream, remote).await;
+    }
+    if !start.starts_with(b"PROXY ") {
+        return Err(invalid("missing PROXY protocol header"));
+    }
+
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() >= V1_MAX {
+            return Err(invalid("PROXY protocol header too long"));
+        }
+        line.push(stream.read_u8().await?);
+    }
+    parse_v1(&line[..line.len() - 2], remote)
+}
+
+fn parse_v1(line: &[u8], remote: SocketAddr) -> io::Result<SocketAddr> {
+    let line = std::str::from_utf8(line).map_err(|_| invalid("PROXY header is not ascii"))?;
+    let mut parts = line.split(' ').skip(1);
+
+    match parts.next() {
+        Some("TCP4" | "TCP6") => (),
+        Some("UNKNOWN") => return Ok(remote),
+        _ => return Err(invalid("unknown PROXY protocol family")),
+    }
+    let (Some(src), Some(_dst), Some(port), Some(_dport), None) = (
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+        parts.next(),
+    ) else {
+        return Err(invalid("malformed PROXY protocol header"));
+    };
+
+    let src: IpAddr = src
+        .parse()
+        .map_err(|_| invalid("bad PROXY source address"))?;
+    let port: u16 = port.parse().map_err(|_| invalid("bad PROXY source port"))?;
+    Ok(SocketAddr::new(src, port))
+}
+
+async fn read_v2<S: AsyncRead + Unpin>(
+    stream: &mut S,
+    remote: SocketAddr,
+) -> io::Result<SocketAddr> {
+    let mut header = vec![0; 4];
+    stream.read_exact(&mut header).await?;
+    // do not wait for a body whose length we cannot trust
+    if header[0] >> 4 == 2 {
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        header.resize(4 + len, 0);
+        stream.read_exact(&mut header[4..]).await?;
+    }
+    parse_v2(&header, remote)
+}
+
+/// header is everything after the v2 signature, the version and command,
+/// family, length and the addresses
+fn parse_v2(header: &[u8], remote: SocketAddr) -> io::Result<SocketAddr> {
+    let (&[ver_cmd, family, len_hi, len_lo], body) = header
+        .split_first_chunk()
+        .ok_or_else(|| invalid("truncated PROXY protocol header"))?;
+    if ver_cmd >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+    if body.len() != u16::from_be_bytes([len_hi, len_lo]) as usize {
+        return Err(invalid("truncated PROXY protocol header"));
+    }
+
+    // LOCAL connections come from the proxy itself
+    if ver_cmd & 0xf == 0 {
+        return Ok(remote);
+    }
+
+    match family >> 4 {
+        1 if body.len() >= 12 => {
+            let ip: [u8; 4] = body[0..4].try_into().unwrap();
+            let port = u16::from_be_bytes([body[8], body[9]]);
+            Ok(SocketAddr::new(Ipv4Addr::from(ip).into(), port))
+        }
+        2 if body.len() >= 36 => {
+            let ip: [u8; 16] = body[0..16].try_into().unwrap();
+            let port = u16::from_be_bytes([body[32], body[33]]);
+            Ok(SocketAddr::new(Ipv6Addr::from(ip).into(), port))
+        }
+        1 | 2 => Err(invalid("truncated PROXY protocol addresses")),
+        // unix sockets and unspecified families have no address for us
+        _ => Ok(remote),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote() -> SocketAddr {
+        "192.0.2.1:4000".parse().unwrap()
+    }
+
+    async fn read(header: &[u8]) -> io::Result<SocketAddr> {
+        read_header(&mut &header[..], remote()).await
+    }
+
+    /// a v2 header after the signature, with the length filled in
+    fn v2(ver_cmd: u8, family: u8, body: &[u8]) -> Vec<u8> {
+        let mut header = vec![ver_cmd, family];
+        header.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        header.extend_from_slice(body);
+        header
+    }
+
+    #[tokio::test]
+    async fn v1_tcp4() {
+        let addr = read(b"PROXY TCP4 198.51.100.7 203.0.113.1 51234 443\r\n").await;
+        assert_eq!(addr.unwrap(), "198.51.100.7:51234".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn v1_tcp6() {
+        let addr = read(b"PROXY TCP6 2001:db8::7 2001:db8::1 51234 443\r\n").await;
+        assert_eq!(addr.unwrap(), "[2001:db8::7]:51234".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn v1_unknown() {
+        assert_eq!(read(b"PROXY UNKNOWN\r\n").await.unwrap(), remote());
+    }
+
+    #[tokio::test]
+    async fn v1_too_long() {
+        let mut line = b"PROXY TCP4 ".to_vec();
+        line.resize(V1_MAX + 10, b'1');
+        line.extend_from_slice(b"\r\n");
+        let e = read(&line).await.unwrap_err();
+        assert_eq!(e.to_string(), "PROXY protocol header too long");
+    }
+
+    #[tokio::test]
+    async fn v1_bad_family() {
+        let e = read(b"PROXY UDP4 198.51.100.7 203.0.113.1 51234 443\r\n")
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "unknown PROXY protocol family");
+    }
+
+    #[tokio::test]
+    async fn v2_through_read_header() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend(v2(
+            0x21,
+            0x11,
+            &[198, 51, 100, 7, 203, 0, 113, 1, 0xc8, 0x22, 1, 0xbb],
+        ));
+        assert_eq!(
+            read(&header).await.unwrap(),
+            "198.51.100.7:51234".parse().unwrap()
+        );
+    }
+
+    #[test]
+    fn v2_proxy_inet() {
+        let header = v2(
+            0x21,
+            0x11,
+            &[198, 51, 100, 7, 203, 0, 113, 1, 0xc8, 0x22, 1, 0xbb],
+        );
+        let addr = parse_v2(&header, remote()).unwrap();
+        assert_eq!(addr, "198.51.100.7:51234".parse().unwrap());
+    }
+
+    #[test]
+    fn v2_proxy_inet6() {
+        let mut body = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 7)
+            .octets()
+            .to_vec();
+        body.extend_from_slice(&Ipv6Addr::LOCALHOST.octets());
+        body.extend_from_slice(&[0xc8, 0x22, 1, 0xbb]);
+        let addr = parse_v2(&v2(0x21, 0x21, &body), remote()).unwrap();
+        assert_eq!(addr, "[2001:db8::7]:51234".parse().unwrap());
+    }
+
+    #[test]
+    fn v2_local() {
+        let header = v2(
+            0x20,
+            0x11,
+            &[198, 51, 100, 7, 203, 0, 113, 1, 0xc8, 0x22, 1, 0xbb],
+        );
+        assert_eq!(parse_v2(&header, remote()).unwrap(), remote());
+    }
+
+    #[test]
+    fn v2_truncated_inet() {
+        let header = v2(0x21, 0x11, &[198, 51, 100, 7, 203, 0, 113, 1]);
+        let e = parse_v2(&header, remote()).unwrap_err();
+        assert_eq!(e.to_string(), "truncated PROXY protocol addresses");
+    }
+
+    #[test]
+    fn v2_truncated_inet6() {
+        let header = v2(0x21, 0x21, &[0; 20]);
+        let e = parse_v2(&header, remote()).unwrap_err();
+        assert_eq!(e.to_string(), "truncated PROXY protocol addresses");
+    }
+
+    #[test]
+    fn v2_shorter_than_its_length() {
+        let mut header = v2(0x21, 0x11, &[0; 12]);
+        header.truncate(10);
+        let e = parse_v2(&header, remote()).unwrap_err();
+        assert_eq!(e.to_string(), "truncated PROXY protocol header");
+    }
+
+    #[test]
+    fn v2_bad_version() {
+        let e = parse_v2(&v2(0x11, 0x11, &[0; 12]), remote()).unwrap_err();
+        assert_eq!(e.to_string(), "unsupported PROXY protocol version");
+    }
+}
@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{Html, IntoResponse},
+};
+
+use crate::AppState;
+
+/// the upload form, with what browsers need to offer installing it
+pub fn form(form: &str) -> String {
+    form.replacen(
+        "</head>",
+        concat!(include_str!("pwa/head.html"), "</head>"),
+        1,
+    )
+}
+
+pub async fn manifest(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "application/manifest+json")],
+        include_str!("pwa/manifest.webmanifest").replace("{form}", &state.opt.form_path),
+    )
+}
+
+/// what the share target answers with once everything is stored
+pub fn shared(state: &AppState) -> Html<String> {
+    Html(include_str!("pwa/shared.html").replace("{form}", &state.opt.form_path))
+}
+
+pub async fn service_worker() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/javascript")],
+        include_str!("pwa/sw.js"),
+    )
+}
+
+pub async fn icon() -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "image/svg+xml")],
+        include_str!("pwa/icon.svg"),
+    )
+}
@@ -0,0 +1,2 @@
+// browsers want a fetch handler before they offer to install
+self.addEventListener("fetch", () => {});
@@ -0,0 +1,298 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    net::IpAddr,
+    path::PathBuf,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+
+use tokio::sync::Notify;
+
+use crate::{cidr::Cidr, units};
+
+/// how many clients we keep usage for before forgetting the quietest
+const MAX_TRACKED: usize = 4096;
+
+/// `--ip-quota`, written like `2GiB/24h`
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaSpec {
+    pub bytes: u64,
+    pub window: Duration,
+}
+
+impl FromStr for QuotaSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (bytes, window) = s
+            .split_once('/')
+            .ok_or_else(|| format!("expected SIZE/DURATION, got {:?}", s))?;
+        Ok(Self {
+            bytes: units::parse_size(bytes)?,
+            window: units::parse_duration(window)?,
+        })
+    }
+}
+
+pub struct Quota {
+    spec: QuotaSpec,
+    exempt: Vec<Cidr>,
+    file: Option<PathBuf>,
+    clients: Mutex<HashMap<IpAddr, Client>>,
+    /// poked whenever usage changes, so --quota-file gets rewritten
+    changed: Notify,
+}
+
+#[derive(Default)]
+struct Client {
+    /// what finished requests wrote, oldest first
+    uses: VecDeque<(SystemTime, u64)>,
+    /// what requests still in progress have taken so far
+    reserved: u64,
+}
+
+/// how much one request may still write, its bytes are reserved in the
+/// quota as they arrive so parallel requests cannot add up to more
+pub struct Budget<'a> {
+    ip: IpAddr,
+    /// None when unlimited
+    quota: Option<&'a Quota>,
+    reserved: u64,
+    pub used: u64,
+}
+
+impl Budget<'_> {
+    pub fn unlimited(ip: IpAddr) -> Self {
+        Self {
+            ip,
+            quota: None,
+            reserved: 0,
+            used: 0,
+        }
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+
+    /// account for n more bytes, failing once the quota is exceeded
+    pub fn take(&mut self, n: u64) -> Result<(), Exceeded> {
+        self.used += n;
+        let Some(quota) = self.quota else {
+            return Ok(());
+        };
+        if self.used <= self.reserved {
+            return Ok(());
+        }
+
+        let more = self.used - self.reserved;
+        let now = SystemTime::now();
+        let mut clients = quota.clients.lock().unwrap();
+        let client = clients.entry(self.ip).or_default();
+        quota.expire(&mut client.uses, now);
+        if client.used() + more > quota.spec.bytes {
+            return Err(quota.exceeded(client, now));
+        }
+        client.reserved += more;
+        self.reserved += more;
+        Ok(())
+    }
+}
+
+/// hands back what was reserved, keeping what was actually written
+impl Drop for Budget<'_> {
+    fn drop(&mut self) {
+        if let Some(quota) = self.quota {
+            quota.record(self.ip, self.reserved, self.used.min(self.reserved));
+        }
+    }
+}
+
+impl Client {
+    fn used(&self) -> u64 {
+        self.uses.iter().map(|(_, b)| b).sum::<u64>() + self.reserved
+    }
+}
+
+pub struct Exceeded {
+    retry: Duration,
+}
+
+impl IntoResponse for Exceeded {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            [(header::RETRY_AFTER, self.retry.as_secs().max(1).to_string())],
+            "upload quota for your address is used up",
+        )
+            .into_response()
+    }
+}
+
+impl From<Exceeded> for Response {
+    fn from(e: Exceeded) -> Self {
+        e.into_response()
+    }
+}
+
+impl Quota {
+    pub fn new(spec: QuotaSpec, exempt: Vec<Cidr>, file: Option<PathBuf>) -> Result<Self, String> {
+        let mut clients = HashMap::new();
+        if let Some(file) = &file {
+            match fs::read_to_string(file) {
+                Ok(saved) => {
+                    for line in saved.lines() {
+                        let mut parts = line.split(' ');
+                        let (Some(ip), Some(time), Some(bytes)) =
+                            (parts.next(), parts.next(), parts.next())
+                        else {
+                            continue;
+                        };
+                        let (Ok(ip), Ok(time), Ok(bytes)) =
+                            (ip.parse(), time.parse(), bytes.parse())
+                        else {
+                            continue;
+                        };
+                        clients
+                            .entry(ip)
+                            .or_insert_with(Client::default)
+                            .uses
+                            .push_back((UNIX_EPOCH + Duration::from_secs(time), bytes));
+                    }
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => return Err(format!("reading {}: {}", file.display(), e)),
+            }
+        }
+
+        Ok(Self {
+            spec,
+            exempt,
+            file,
+            clients: Mutex::new(clients),
+            changed: Notify::new(),
+        })
+    }
+
+    /// start a request's budget, rejecting it straight away if the
+    /// announced length cannot fit
+    pub fn budget(&self, ip: IpAddr, length: Option<u64>) -> Result<Budget<'_>, Exceeded> {
+        let ip = ip.to_canonical();
+        if self.exempt.iter().any(|c| c.contains(ip)) {
+            return Ok(Budget::unlimited(ip));
+        }
+
+        let now = SystemTime::now();
+        let mut clients = self.clients.lock().unwrap();
+        if let Some(client) = clients.get_mut(&ip) {
+            self.expire(&mut client.uses, now);
+            let remaining = self.spec.bytes.saturating_sub(client.used());
+            if remaining == 0 || length.is_some_and(|l| l > remaining) {
+                return Err(self.exceeded(client, now));
+            }
+        } else if length.is_some_and(|l| l > self.spec.bytes) {
+            return Err(Exceeded {
+                retry: self.spec.window,
+            });
+        }
+
+        Ok(Budget {
+            ip,
+            quota: Some(self),
+            reserved: 0,
+            used: 0,
+        })
+    }
+
+    /// hint at when the oldest upload stops counting, or at a whole
+    /// window when it is all still in progress
+    fn exceeded(&self, client: &Client, now: SystemTime) -> Exceeded {
+        let retry = client
+            .uses
+            .front()
+            .and_then(|(t, _)| (*t + self.spec.window).duration_since(now).ok())
+            .unwrap_or(self.spec.window);
+        Exceeded { retry }
+    }
+
+    fn expire(&self, uses: &mut VecDeque<(SystemTime, u64)>, now: SystemTime) {
+        while uses
+            .front()
+            .is_some_and(|(t, _)| now.duration_since(*t).unwrap_or_default() >= self.spec.window)
+        {
+            uses.pop_front();
+        }
+    }
+
+    /// turn what a finished request reserved into what it wrote
+    fn record(&self, ip: IpAddr, reserved: u64, written: u64) {
+        if reserved == 0 {
+            return;
+        }
+
+        let now = SystemTime::now();
+        let mut clients = self.clients.lock().unwrap();
+        let client = clients.entry(ip).or_default();
+        client.reserved -= reserved;
+        if written > 0 {
+            client.uses.push_back((now, written));
+        }
+
+        if clients.len() > MAX_TRACKED {
+            clients.retain(|_, client| {
+                self.expire(&mut client.uses, now);
+                !client.uses.is_empty() || client.reserved > 0
+            });
+            if clients.len() > MAX_TRACKED {
+                let quietest = clients
+                    .iter()
+                    .filter(|(_, client)| client.reserved == 0)
+                    .min_by_key(|(_, client)| client.uses.back().map(|(t, _)| *t))
+                    .map(|(ip, _)| *ip);
+                if let Some(quietest) = quietest {
+                    clients.remove(&quietest);
+                }
+            }
+        }
+        if written > 0 {
+            self.changed.notify_one();
+        }
+    }
+
+    /// keep --quota-file up to date, away from the requests that change
+    /// the usage
+    pub async fn persist(&self) {
+        loop {
+            self.changed.notified().await;
+            tokio::task::block_in_place(|| self.save());
+        }
+    }
+
+    pub fn persisting(&self) -> bool {
+        self.file.is_some()
+    }
+
+    pub fn save(&self) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let mut saved = String::new();
+        for (ip, client) in self.clients.lock().unwrap().iter() {
+            for (time, bytes) in &client.uses {
+                let time = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                saved.push_str(&format!("{} {} {}\n", ip, time.as_secs(), bytes));
+            }
+        }
+        let tmp = file.with_extension("tmp");
+        if let Err(e) = fs::write(&tmp, saved).and_then(|_| fs::rename(&tmp, file)) {
+            eprintln!("error saving quota to {}: {}", file.display(), e);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::{path::Path, str::FromStr};
+
+/// `--route-ext jpg,png=images`, sends uploads with these extensions to a
+/// subdirectory
+#[derive(Debug, Clone)]
+pub struct Route {
+    exts: Vec<String>,
+    pub dir: String,
+}
+
+impl FromStr for Route {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (exts, dir) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected EXT,EXT=DIR, got {:?}", s))?;
+        let exts: Vec<String> = exts
+            .split(',')
+            .map(|e| e.trim().trim_start_matches('.').to_lowercase())
+            .filter(|e| !e.is_empty())
+            .collect();
+        if exts.is_empty() {
+            return Err(format!("no extensions in {:?}", s));
+        }
+        Ok(Self {
+            exts,
+            dir: parse_dir(dir)?,
+        })
+    }
+}
+
+/// a directory below the upload directory, that cannot climb out of it
+pub fn parse_dir(s: &str) -> Result<String, String> {
+    let dir = s.trim_end_matches('/');
+    if dir.is_empty()
+        || dir.starts_with('/')
+        || dir
+            .split('/')
+            .any(|c| c.is_empty() || c == "." || c == "..")
+    {
+        return Err(format!(
+            "{:?} must be a relative directory without . or .. in it",
+            s
+        ));
+    }
+    Ok(dir.to_string())
+}
+
+/// where an upload called name goes, None for the upload directory itself
+pub fn dir_for<'a>(routes: &'a [Route], default: Option<&'a str>, name: &str) -> Option<&'a str> {
+    let name = name.to_lowercase();
+    routes
+        .iter()
+        .find(|r| {
+            r.exts.iter().any(|e| {
+                name.strip_suffix(e.as_str())
+                    .is_some_and(|n| n.ends_with('.'))
+            })
+        })
+        .map(|r| r.dir.as_str())
+        .or(default)
+}
+
+/// dir and every directory above it, outermost first
+pub fn parts(dir: &str) -> impl Iterator<Item = &str> {
+    dir.match_indices('/').map(|(i, _)| &dir[..i]).chain([dir])
+}
+
+/// the first part of dir that exists but is not a directory, which is why
+/// creating dir would fail
+pub fn file_in_the_way(dir: &str) -> Option<&str> {
+    parts(dir).find(|part| Path::new(part).metadata().is_ok_and(|m| !m.is_dir()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_in_the_way_of_a_directory() {
+        let tmp = std::env::temp_dir().join(format!("quickshare-route-{}", std::process::id()));
+        std::fs::create_dir_all(&tmp).unwrap();
+        let tmp = tmp.to_str().unwrap();
+
+        // upload a, then a/b
+        std::fs::write(format!("{}/a", tmp), "a").unwrap();
+        let dir = format!("{}/a/b", tmp);
+        assert!(std::fs::create_dir_all(&dir).is_err());
+        assert_eq!(file_in_the_way(&dir), Some(format!("{}/a", tmp).as_str()));
+
+        let dir = format!("{}/c/d", tmp);
+        assert_eq!(file_in_the_way(&dir), None);
+        std::fs::create_dir_all(&dir).unwrap();
+        assert_eq!(file_in_the_way(&dir), None);
+
+        std::fs::remove_dir_all(tmp).unwrap();
+    }
+}
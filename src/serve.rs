@@ -0,0 +1,121 @@
+use std::{io, net::SocketAddr, sync::Arc, time::Duration};
+
+use axum::{extract::ConnectInfo, Router};
+use hyper::{body::Incoming, server::conn::http1, service::service_fn, Request};
+use hyper_util::rt::{TokioIo, TokioTimer};
+use tokio::{net::TcpListener, sync::watch, task::JoinSet};
+use tower::Service;
+
+use crate::{idle::Idle, proxy, AppState};
+
+const PROXY_HEADER_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn is_connection_error(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+async fn stopped(shutdown: &mut watch::Receiver<bool>) {
+    _ = shutdown.wait_for(|&s| s).await;
+}
+
+/// accept loop, so we get a say in connections before they reach the router
+///
+/// returns once AppState::shutdown fires and every open connection has
+/// finished what it was doing
+pub async fn serve(listener: TcpListener, app: Router, state: Arc<AppState>) {
+    let mut shutdown = state.shutdown.subscribe();
+    let mut conns = JoinSet::new();
+
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = stopped(&mut shutdown) => break,
+            Some(_) = conns.join_next() => continue,
+        };
+        let (mut stream, remote) = match accepted {
+            Ok(v) => v,
+            Err(e) if is_connection_error(&e) => continue,
+            Err(e) => {
+                // probably out of file descriptors, give things a moment
+                eprintln!("error accepting {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+
+        if state.opt.nodelay {
+            _ = stream.set_nodelay(true);
+        }
+
+        let app = app.clone();
+        let state = state.clone();
+        let mut shutdown = state.shutdown.subscribe();
+        conns.spawn(async move {
+            // read in here so a slow proxy cannot hold up the accept loop
+            let remote = if state.opt.proxy_protocol {
+                let header = tokio::time::timeout(
+                    PROXY_HEADER_TIMEOUT,
+                    proxy::read_header(&mut stream, remote),
+                );
+                match header.await {
+                    Ok(Ok(client)) => client,
+                    Ok(Err(e)) => {
+                        eprintln!("error from {}: {}", remote, e);
+                        return;
+                    }
+                    Err(_) => return,
+                }
+            } else {
+                remote
+            };
+            if let Some(bans) = &state.bans {
+                if bans.is_banned(remote.ip()) {
+                    return;
+                }
+            }
+
+            let service = service_fn(move |mut req: Request<Incoming>| {
+                req.extensions_mut()
+                    .insert(ConnectInfo::<SocketAddr>(remote));
+                app.clone().call(req)
+            });
+            let (stream, activity) = Idle::new(stream);
+            let keepalive = state
+                .opt
+                .keepalive
+                .filter(|&k| k > 0)
+                .map(Duration::from_secs);
+            let conn = http1::Builder::new()
+                .keep_alive(state.opt.keepalive != Some(0))
+                .timer(TokioTimer::new())
+                .header_read_timeout(Duration::from_secs(state.opt.header_timeout))
+                .serve_connection(TokioIo::new(stream), service)
+                .with_upgrades();
+            tokio::pin!(conn);
+
+            loop {
+                let idle_for = keepalive.map(|k| k.saturating_sub(activity.since()));
+                tokio::select! {
+                    _ = conn.as_mut() => return,
+                    _ = stopped(&mut shutdown) => break,
+                    // a graceful shutdown still lets a request that is
+                    // quietly waiting on a slow stdin or client finish
+                    _ = tokio::time::sleep(idle_for.unwrap_or_default()), if idle_for.is_some() => {
+                        if activity.since() >= keepalive.unwrap() {
+                            break;
+                        }
+                    }
+                }
+            }
+            conn.as_mut().graceful_shutdown();
+            _ = conn.await;
+        });
+    }
+
+    while conns.join_next().await.is_some() {}
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use futures_util::StreamExt;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    audit, budget, conflict, error_status, file_name, finished, require_host, AppState, Output,
+};
+
+/// how many resumable uploads may be open at once, as each one holds a
+/// file open until it completes or expires
+const MAX_OPEN: usize = 256;
+/// and how many of those a single address may hold
+const MAX_PER_IP: usize = 16;
+
+/// an upload spread over several requests, for when connections are
+/// too flaky to get a big file through in one go
+struct Session {
+    /// what the client called it, for --mirror
+    original: String,
+    name: String,
+    conflict: conflict::Policy,
+    /// None once the session has been completed or thrown away
+    output: Option<Output>,
+    hasher: Sha256,
+    len: u64,
+    touched: Instant,
+}
+
+type Shared = Arc<tokio::sync::Mutex<Session>>;
+
+pub struct Sessions {
+    ttl: Duration,
+    /// by id, along with who started them
+    open: Mutex<HashMap<String, (IpAddr, Shared)>>,
+}
+
+fn io_error(e: std::io::Error) -> Response {
+    eprintln!("error {:?}", e);
+    (error_status(&e), e.to_string()).into_response()
+}
+
+impl Sessions {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            open: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn get(&self, id: &str) -> Result<Shared, (StatusCode, &'static str)> {
+        self.open
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|(_, session)| session.clone())
+            .ok_or((StatusCode::NOT_FOUND, "no such session"))
+    }
+
+    /// throw away sessions nobody has touched within the ttl
+    pub fn expire(&self, state: &AppState) {
+        self.discard(state, self.ttl);
+    }
+
+    /// throw away every unfinished session, when shutting down
+    pub fn close(&self, state: &AppState) {
+        self.discard(state, Duration::ZERO);
+    }
+
+    fn discard(&self, state: &AppState, idle: Duration) {
+        let mut open = self.open.lock().unwrap();
+        open.retain(|_, (_, session)| {
+            // busy sessions are clearly not abandoned
+            let Ok(mut session) = session.try_lock() else {
+                return true;
+            };
+            if session.touched.elapsed() < idle {
+                return true;
+            }
+            if let Some(output) = session.output.take() {
+                eprintln!("abandoned upload of {}", session.name);
+                output.discard(state, &session.name);
+            }
+            false
+        });
+    }
+}
+
+fn new_id() -> String {
+    let mut id = [0; 16];
+    getrandom::fill(&mut id).expect("no randomness for session ids");
+    id.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// `Content-Range: bytes START-END/TOTAL`, TOTAL may be `*`
+fn content_range(headers: &HeaderMap) -> Result<(u64, u64), (StatusCode, &'static str)> {
+    let bad = (
+        StatusCode::BAD_REQUEST,
+        "expected a content-range like bytes 0-1023/*",
+    );
+    let range = headers
+        .get(header::CONTENT_RANGE)
+        .and_then(|r| r.to_str().ok())
+        .and_then(|r| r.strip_prefix("bytes "))
+        .ok_or(bad)?;
+    let (range, _total) = range.split_once('/').ok_or(bad)?;
+    let (start, end) = range.split_once('-').ok_or(bad)?;
+    let (Ok(start), Ok(end)) = (start.parse(), end.parse()) else {
+        return Err(bad);
+    };
+    if end < start {
+        return Err(bad);
+    }
+    Ok((start, end))
+}
+
+#[derive(Deserialize)]
+pub struct Start {
+    name: String,
+}
+
+/// POST /session?name=NAME, answers with the session id
+pub async fn start(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Query(start): Query<Start>,
+    headers: HeaderMap,
+) -> Result<String, Response> {
+    require_host(&state, &headers).map_err(IntoResponse::into_response)?;
+    let sessions = state.sessions.as_ref().unwrap();
+    sessions.expire(&state);
+
+    let conflict = conflict::for_request(state.opt.on_conflict, &headers);
+    let name = file_name(&state, &start.name).await?;
+    let ip = remote.ip().to_canonical();
+    // held while staging, so the limits cannot be raced past
+    let mut open = sessions.open.lock().unwrap();
+    if open.len() >= MAX_OPEN {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "too many resumable uploads in progress",
+        )
+            .into_response());
+    }
+    if open.values().filter(|(owner, _)| *owner == ip).count() >= MAX_PER_IP {
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            "too many resumable uploads in progress from your address",
+        )
+            .into_response());
+    }
+    let (output, name) = Output::open(&state, &name, conflict, true).map_err(io_error)?;
+    let id = new_id();
+    open.insert(
+        id.clone(),
+        (
+            ip,
+            Arc::new(tokio::sync::Mutex::new(Session {
+                original: start.name,
+                name,
+                conflict,
+                output: Some(output),
+                hasher: Sha256::new(),
+                len: 0,
+                touched: Instant::now(),
+            })),
+        ),
+    );
+    Ok(id)
+}
+
+/// GET /session/ID, how many bytes arrived so far, to resume from
+pub async fn offset(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<String, Response> {
+    require_host(&state, &headers).map_err(IntoResponse::into_response)?;
+    let session = state
+        .sessions
+        .as_ref()
+        .unwrap()
+        .get(&id)
+        .map_err(IntoResponse::into_response)?;
+    let len = session.lock().await.len;
+    Ok(len.to_string())
+}
+
+/// PATCH /session/ID with a Content-Range, appends to the upload
+///
+/// whatever arrives is kept even if the connection drops halfway, the
+/// answer is always the new offset
+pub async fn append(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<String, Response> {
+    require_host(&state, &headers).map_err(IntoResponse::into_response)?;
+    let session = state
+        .sessions
+        .as_ref()
+        .unwrap()
+        .get(&id)
+        .map_err(IntoResponse::into_response)?;
+    let Ok(mut session) = session.try_lock() else {
+        return Err((
+            StatusCode::CONFLICT,
+            "this session is already being appended to",
+        )
+            .into_response());
+    };
+    let session = &mut *session;
+    session.touched = Instant::now();
+
+    let (start, end) = content_range(&headers).map_err(IntoResponse::into_response)?;
+    if start != session.len {
+        return Err((
+            StatusCode::RANGE_NOT_SATISFIABLE,
+            format!("session is at byte {}", session.len),
+        )
+            .into_response());
+    }
+    if end >= state.opt.limit as u64 * 1048576 {
+        return Err((StatusCode::PAYLOAD_TOO_LARGE, "upload is over the limit").into_response());
+    }
+    let Some(output) = &mut session.output else {
+        return Err((StatusCode::NOT_FOUND, "no such session").into_response());
+    };
+
+    let mut budget = budget(&state, remote, &headers)?;
+    let mut stream = body.into_data_stream();
+    let mut res = Ok(());
+    while let Some(chunk) = stream.next().await {
+        let Ok(chunk) = chunk else {
+            break;
+        };
+        let room = (end + 1 - session.len) as usize;
+        let chunk = &chunk[..chunk.len().min(room)];
+        if let Err(e) = budget.take(chunk.len() as u64) {
+            res = Err(e.into());
+            break;
+        }
+        if let Err(e) = output.write_all(chunk) {
+            budget.used -= chunk.len() as u64;
+            res = Err(io_error(e));
+            break;
+        }
+        session.hasher.update(chunk);
+        session.len += chunk.len() as u64;
+        if session.len > end {
+            break;
+        }
+    }
+    session.touched = Instant::now();
+
+    res.map(|_| session.len.to_string())
+}
+
+/// POST /session/ID/complete, moves the upload to its real name
+pub async fn complete(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(id): Path<String>,
+    headers: HeaderMap,
+) -> Result<&'static str, Response> {
+    require_host(&state, &headers).map_err(IntoResponse::into_response)?;
+    let sessions = state.sessions.as_ref().unwrap();
+    let session = sessions.get(&id).map_err(IntoResponse::into_response)?;
+    let Ok(mut session) = session.try_lock() else {
+        return Err((
+            StatusCode::CONFLICT,
+            "this session is still being appended to",
+        )
+            .into_response());
+    };
+    sessions.open.lock().unwrap().remove(&id);
+    let Some(output) = session.output.take() else {
+        return Err((StatusCode::NOT_FOUND, "no such session").into_response());
+    };
+
+    let name = session.name.clone();
+    if let Err(e) = output.validate(&state, &name) {
+        output.discard(&state, &name);
+        return Err(e.into_response());
+    }
+    let res = output.commit(&state, &name, session.conflict);
+    audit::log(
+        &state,
+        audit::Event {
+            event: "upload",
+            ip: remote.ip().to_canonical(),
+            target: res.as_ref().map_or(&name, |(_, name)| name),
+            bytes: Some(session.len),
+            outcome: res
+                .as_ref()
+                .map_or_else(error_status, |_| StatusCode::OK)
+                .as_u16(),
+        },
+    );
+    let (file, name) = res.map_err(io_error)?;
+    if state.opt.write_checksums {
+        let hash = std::mem::take(&mut session.hasher).finalize();
+        file.write_checksum(&state, &name, &hash, session.conflict);
+    }
+
+    finished(
+        &state,
+        &name,
+        session.len,
+        remote.ip().to_canonical(),
+        &session.original,
+    );
+    Ok("uploaded~")
+}
@@ -0,0 +1,74 @@
+use std::{
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use axum::http::StatusCode;
+
+use crate::AppState;
+
+/// --stdout, where uploads go to standard output instead of disk
+pub struct Sink {
+    busy: AtomicBool,
+    done: AtomicBool,
+}
+
+/// held for as long as one upload is being written out
+pub struct Claim<'a> {
+    sink: &'a Sink,
+}
+
+impl Sink {
+    pub fn new() -> Self {
+        Self {
+            busy: AtomicBool::new(false),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    /// only one upload may write at a time, or outputs would interleave
+    pub fn claim(&self) -> Result<Claim<'_>, (StatusCode, &'static str)> {
+        if self.busy.swap(true, Ordering::SeqCst) {
+            return Err((StatusCode::CONFLICT, "already receiving an upload"));
+        }
+        let claim = Claim { sink: self };
+        if self.done.load(Ordering::SeqCst) {
+            return Err((StatusCode::GONE, "already received an upload"));
+        }
+        Ok(claim)
+    }
+
+    /// an upload failed after part of it already went out, anything
+    /// written after it would look like it belonged to it
+    pub fn spoil(&self, state: &AppState) {
+        self.done.store(true, Ordering::SeqCst);
+        eprintln!("upload failed partway, stdout is incomplete, shutting down");
+        state.failed.store(true, Ordering::SeqCst);
+        state.shutdown.send_replace(true);
+    }
+}
+
+impl Claim<'_> {
+    /// call once an upload made it out in full
+    pub fn finish(self, state: &AppState) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+        if state.opt.keep_listening {
+            if let Some(delimiter) = &state.opt.delimiter {
+                stdout.write_all(delimiter.as_bytes())?;
+            }
+            return stdout.flush();
+        }
+        stdout.flush()?;
+
+        self.sink.done.store(true, Ordering::SeqCst);
+        eprintln!("upload received, shutting down");
+        state.shutdown.send_replace(true);
+        Ok(())
+    }
+}
+
+impl Drop for Claim<'_> {
+    fn drop(&mut self) {
+        self.sink.busy.store(false, Ordering::SeqCst);
+    }
+}
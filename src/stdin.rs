@@ -0,0 +1,245 @@
+use std::{
+    io,
+    net::SocketAddr,
+    path::PathBuf,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Request, State},
+    http::{header, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use http_body::{Frame, SizeHint};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncWriteExt},
+};
+use tower::ServiceExt;
+use tower_http::services::ServeFile;
+
+use crate::{audit, mimes, AppState};
+
+/// what --stdin is sharing
+pub struct Shared {
+    /// where stdin got buffered to, None when streaming it
+    pub path: Option<PathBuf>,
+    mime: mime_guess::Mime,
+    /// downloads left before we exit
+    left: AtomicUsize,
+    /// a stream can only be handed out once
+    taken: AtomicBool,
+}
+
+impl Shared {
+    pub async fn new(
+        name: &str,
+        mime: Option<&str>,
+        overrides: &[mimes::Override],
+        count: usize,
+        stream: bool,
+        limit: u64,
+    ) -> io::Result<Self> {
+        let mime = match mime {
+            Some(m) => m
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "invalid --type"))?,
+            None => mimes::guess(overrides, name),
+        };
+        let path = if stream {
+            None
+        } else {
+            Some(buffer(name, limit).await?)
+        };
+
+        Ok(Self {
+            path,
+            mime,
+            left: AtomicUsize::new(if stream { 1 } else { count }),
+            taken: AtomicBool::new(false),
+        })
+    }
+}
+
+/// stdin cannot be rewound, so keep it in a temporary file to allow
+/// repeated and ranged downloads
+async fn buffer(name: &str, limit: u64) -> io::Result<PathBuf> {
+    let path = std::env::temp_dir().join(format!("quickshare_{}_{}", std::process::id(), name));
+    let mut file = File::create_new(&path).await?;
+
+    let written = tokio::io::copy(&mut tokio::io::stdin().take(limit + 1), &mut file).await;
+    let written = match written {
+        Ok(w) => w,
+        Err(e) => {
+            _ = std::fs::remove_file(&path);
+            return Err(e);
+        }
+    };
+    if written > limit {
+        _ = std::fs::remove_file(&path);
+        return Err(io::Error::new(
+            io::ErrorKind::FileTooLarge,
+            "stdin is larger than the upload limit",
+        ));
+    }
+    file.flush().await?;
+
+    Ok(path)
+}
+
+pub async fn download(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    req: Request,
+) -> Response {
+    let shared = state.stdin.as_ref().unwrap();
+    let counts = req.method() == Method::GET;
+
+    let res = match &shared.path {
+        Some(path) => ServeFile::new_with_mime(path, &shared.mime)
+            .oneshot(req)
+            .await
+            .into_response(),
+        None => {
+            if req.method() == Method::HEAD {
+                return ([(header::CONTENT_TYPE, shared.mime.as_ref())]).into_response();
+            }
+            if shared.taken.swap(true, Ordering::SeqCst) {
+                return (StatusCode::GONE, "already sent to someone else").into_response();
+            }
+            let stdin = futures_util::stream::unfold(tokio::io::stdin(), |mut stdin| async {
+                let mut buf = vec![0; 65536];
+                match stdin.read(&mut buf).await {
+                    Ok(0) => None,
+                    Ok(n) => {
+                        buf.truncate(n);
+                        Some((Ok::<_, io::Error>(Bytes::from(buf)), stdin))
+                    }
+                    Err(e) => Some((Err(e), stdin)),
+                }
+            });
+            (
+                [(header::CONTENT_TYPE, shared.mime.as_ref())],
+                Body::from_stream(stdin),
+            )
+                .into_response()
+        }
+    };
+
+    let length = res
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|l| l.to_str().ok())
+        .and_then(|l| l.parse().ok());
+    audit::log(
+        &state,
+        audit::Event {
+            event: "download",
+            ip: remote.ip().to_canonical(),
+            target: state.opt.stdin.as_deref().unwrap_or_default(),
+            bytes: length,
+            outcome: res.status().as_u16(),
+        },
+    );
+
+    // only complete full downloads count towards --count
+    if !counts || res.status() != StatusCode::OK {
+        return res;
+    }
+    // a stream cannot be handed out again, so a download cut short
+    // leaves nothing more to do but say so
+    let aborted: Option<Box<dyn FnOnce() + Send>> = match shared.path {
+        Some(_) => None,
+        None => {
+            let state = state.clone();
+            Some(Box::new(move || {
+                eprintln!("download of stdin cut short, shutting down");
+                state.failed.store(true, Ordering::SeqCst);
+                state.shutdown.send_replace(true);
+            }))
+        }
+    };
+    res.map(|body| {
+        Body::new(Watched {
+            inner: body,
+            left: length,
+            done: Some(Box::new(move || {
+                let shared = state.stdin.as_ref().unwrap();
+                if shared.left.fetch_sub(1, Ordering::SeqCst) == 1 {
+                    eprintln!("all downloads done, shutting down");
+                    state.shutdown.send_replace(true);
+                }
+            })),
+            aborted,
+        })
+    })
+}
+
+/// body that lets us know once it has been sent in full
+struct Watched {
+    inner: Body,
+    /// bytes still to be sent, when known up front
+    left: Option<u64>,
+    done: Option<Box<dyn FnOnce() + Send>>,
+    /// called instead of done when the body is dropped before it was
+    /// sent in full
+    aborted: Option<Box<dyn FnOnce() + Send>>,
+}
+
+impl Watched {
+    fn finish(&mut self) {
+        if let Some(done) = self.done.take() {
+            self.aborted = None;
+            done();
+        }
+    }
+}
+
+impl Drop for Watched {
+    fn drop(&mut self) {
+        if let Some(aborted) = self.aborted.take() {
+            aborted();
+        }
+    }
+}
+
+impl http_body::Body for Watched {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let res = Pin::new(&mut self.inner).poll_frame(cx);
+        match &res {
+            Poll::Ready(None) => self.finish(),
+            Poll::Ready(Some(Ok(frame))) => {
+                let sent = frame.data_ref().map_or(0, |d| d.len() as u64);
+                if let Some(left) = &mut self.left {
+                    *left = left.saturating_sub(sent);
+                }
+                // hyper stops polling once it has everything it expects
+                if self.left == Some(0) || self.inner.is_end_stream() {
+                    self.finish();
+                }
+            }
+            _ => (),
+        }
+        res
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,41 @@
+use std::time::Duration;
+
+/// parse a byte count like `512`, `50MiB` or `2G`
+pub fn parse_size(s: &str) -> Result<u64, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let num: u64 = num.parse().map_err(|_| format!("invalid size {:?}", s))?;
+    let mult: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "t" | "tib" => 1 << 40,
+        "kb" => 1000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "tb" => 1_000_000_000_000,
+        _ => return Err(format!("unknown size unit in {:?}", s)),
+    };
+    num.checked_mul(mult)
+        .ok_or_else(|| format!("size {:?} is too large", s))
+}
+
+/// parse a duration like `90`, `30s`, `15m`, `24h` or `7d`
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (num, unit) = s.split_at(split);
+    let num: u64 = num
+        .parse()
+        .map_err(|_| format!("invalid duration {:?}", s))?;
+    let mult = match unit.trim() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("unknown duration unit in {:?}", s)),
+    };
+    num.checked_mul(mult)
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("duration {:?} is too large", s))
+}
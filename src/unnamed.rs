@@ -0,0 +1,71 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::http::StatusCode;
+use clap::ValueEnum;
+
+/// `--unnamed-policy`, what to call uploads that come without a file name
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Policy {
+    /// the same name every time, so only the first one fits
+    Untitled,
+    Random,
+    Timestamp,
+    Reject,
+}
+
+pub fn name(policy: Policy) -> Result<String, (StatusCode, &'static str)> {
+    match policy {
+        Policy::Untitled => Ok("untitled".to_string()),
+        Policy::Random => {
+            let mut id = [0; 8];
+            getrandom::fill(&mut id).expect("no randomness for file names");
+            Ok(id.iter().map(|b| format!("{:02x}", b)).collect())
+        }
+        Policy::Timestamp => {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            Ok(format!("{}-{:09}", now.as_secs(), now.subsec_nanos()))
+        }
+        Policy::Reject => Err((StatusCode::BAD_REQUEST, "the file needs a name")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn untitled() {
+        assert_eq!(name(Policy::Untitled).unwrap(), "untitled");
+        assert_eq!(name(Policy::Untitled).unwrap(), "untitled");
+    }
+
+    #[test]
+    fn random() {
+        let a = name(Policy::Random).unwrap();
+        let b = name(Policy::Random).unwrap();
+        assert_eq!(a.len(), 16);
+        assert!(a.chars().all(|c| c.is_ascii_hexdigit()));
+        assert_ne!(a, b);
+    }
+
+    #[test]
+    fn timestamp() {
+        let before = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let stamp = name(Policy::Timestamp).unwrap();
+        let (secs, nanos) = stamp.split_once('-').unwrap();
+        assert!(secs.parse::<u64>().unwrap() >= before);
+        assert_eq!(nanos.len(), 9);
+        assert!(nanos.parse::<u32>().is_ok());
+    }
+
+    #[test]
+    fn reject() {
+        let (status, _) = name(Policy::Reject).unwrap_err();
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
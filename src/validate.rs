@@ -0,0 +1,167 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+};
+
+use axum::http::StatusCode;
+use clap::ValueEnum;
+
+use crate::error_status;
+
+/// `--validate`, what every upload has to be
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum Kind {
+    /// png, jpeg, gif, webp, bmp, tiff, ico or avif/heic
+    Image,
+    Zip,
+    Json,
+    Utf8,
+}
+
+trait Validator {
+    /// Ok(false) when the contents are not what they claim to be
+    fn valid(&self, file: &mut File) -> io::Result<bool>;
+}
+
+struct Image;
+struct Zip;
+struct Json;
+struct Utf8;
+
+impl Validator for Image {
+    fn valid(&self, file: &mut File) -> io::Result<bool> {
+        let mut head = [0; 16];
+        let len = read_up_to(file, &mut head)?;
+        let head = &head[..len];
+        Ok(
+            // the first chunk of a png is always its header
+            head.starts_with(b"\x89PNG\r\n\x1a\n") && head.get(12..16) == Some(b"IHDR")
+                || head.starts_with(b"\xff\xd8\xff")
+                || head.starts_with(b"GIF87a")
+                || head.starts_with(b"GIF89a")
+                || head.starts_with(b"RIFF") && head.get(8..12) == Some(b"WEBP")
+                || head.starts_with(b"BM") && len >= 14
+                || head.starts_with(b"II*\0")
+                || head.starts_with(b"MM\0*")
+                || head.starts_with(b"\0\0\x01\0") && len >= 6
+                || matches!(
+                    head.get(4..12),
+                    Some(b"ftypavif" | b"ftypavis" | b"ftypheic" | b"ftypheix" | b"ftypmif1")
+                ),
+        )
+    }
+}
+
+impl Validator for Zip {
+    /// finds the end of central directory record, and checks that what
+    /// it points at really is the central directory
+    fn valid(&self, file: &mut File) -> io::Result<bool> {
+        const EOCD: usize = 22;
+        let size = file.metadata()?.len();
+        // the record is followed by a comment of up to 64KiB
+        let tail_len = size.min((EOCD + 65535) as u64);
+        let mut tail = vec![0; tail_len as usize];
+        file.seek(SeekFrom::End(-(tail_len as i64)))?;
+        file.read_exact(&mut tail)?;
+
+        let Some(at) = tail
+            .windows(4)
+            .rposition(|w| w == b"PK\x05\x06")
+            .filter(|&at| at + EOCD <= tail.len())
+        else {
+            return Ok(false);
+        };
+        let record = &tail[at..at + EOCD];
+        let entries = u16::from_le_bytes([record[10], record[11]]);
+        let cd_size = u32::from_le_bytes(record[12..16].try_into().unwrap());
+        let cd_offset = u32::from_le_bytes(record[16..20].try_into().unwrap());
+        if cd_offset == u32::MAX {
+            // zip64 keeps the real numbers elsewhere, a locator right
+            // before the record is as far as we go
+            return Ok(at >= 20 && &tail[at - 20..at - 16] == b"PK\x06\x07");
+        }
+        let record_at = size - tail_len + at as u64;
+        if cd_offset as u64 + cd_size as u64 > record_at {
+            return Ok(false);
+        }
+        if entries == 0 {
+            return Ok(true);
+        }
+        let mut sig = [0; 4];
+        file.seek(SeekFrom::Start(cd_offset as u64))?;
+        file.read_exact(&mut sig)?;
+        Ok(&sig == b"PK\x01\x02")
+    }
+}
+
+impl Validator for Json {
+    fn valid(&self, file: &mut File) -> io::Result<bool> {
+        let reader = BufReader::new(file);
+        match serde_json::from_reader::<_, serde::de::IgnoredAny>(reader) {
+            Ok(_) => Ok(true),
+            Err(e) if e.is_io() => Err(e.into()),
+            Err(_) => Ok(false),
+        }
+    }
+}
+
+impl Validator for Utf8 {
+    fn valid(&self, file: &mut File) -> io::Result<bool> {
+        let mut buf = vec![0; 65536];
+        // bytes of a character cut in half by the end of the last read
+        let mut carry = 0;
+        loop {
+            let n = file.read(&mut buf[carry..])?;
+            if n == 0 {
+                return Ok(carry == 0);
+            }
+            let filled = carry + n;
+            match std::str::from_utf8(&buf[..filled]) {
+                Ok(_) => carry = 0,
+                Err(e) if e.error_len().is_none() => {
+                    let valid = e.valid_up_to();
+                    buf.copy_within(valid..filled, 0);
+                    carry = filled - valid;
+                }
+                Err(_) => return Ok(false),
+            }
+        }
+    }
+}
+
+fn read_up_to(file: &mut File, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match file.read(&mut buf[len..])? {
+            0 => break,
+            n => len += n,
+        }
+    }
+    Ok(len)
+}
+
+/// check the file at path, Err with what to tell the client when it is
+/// not a valid kind
+pub fn check(kind: Kind, path: &str) -> Result<(), (StatusCode, String)> {
+    let validator: &dyn Validator = match kind {
+        Kind::Image => &Image,
+        Kind::Zip => &Zip,
+        Kind::Json => &Json,
+        Kind::Utf8 => &Utf8,
+    };
+    let res = File::open(path).and_then(|mut file| validator.valid(&mut file));
+    match res {
+        Ok(true) => Ok(()),
+        Ok(false) => Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            format!(
+                "upload is not valid {}",
+                kind.to_possible_value().unwrap().get_name()
+            ),
+        )),
+        Err(e) => {
+            eprintln!("error {:?}", e);
+            Err((error_status(&e), e.to_string()))
+        }
+    }
+}
@@ -0,0 +1,106 @@
+// every test file only uses some of these
+#![allow(dead_code)]
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// kills quickshare when the test is done, even when it failed
+pub struct Server(pub Child);
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        _ = self.0.kill();
+        _ = self.0.wait();
+    }
+}
+
+pub fn wait_for(path: &PathBuf) -> String {
+    let start = Instant::now();
+    loop {
+        if let Ok(ports) = std::fs::read_to_string(path) {
+            return ports;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10), "no port file");
+        sleep(Duration::from_millis(20));
+    }
+}
+
+/// an empty directory of its own for one test
+pub fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("quickshare-{}-{}", test, std::process::id()));
+    _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// start quickshare in dir on a port of its choosing, and wait until it
+/// listens there
+pub fn spawn(dir: &Path, args: &[&str]) -> (Server, u16) {
+    let port_file = dir.join(".ports");
+    let server = Server(
+        Command::new(env!("CARGO_BIN_EXE_quickshare"))
+            .args(["-b", "127.0.0.1:0", "--no-warnings", "--port-file"])
+            .arg(&port_file)
+            .args(args)
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let port = wait_for(&port_file).trim().parse().unwrap();
+    (server, port)
+}
+
+/// send a raw request, and return the whole response
+pub fn request(port: u16, req: &[u8]) -> String {
+    let mut conn = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    conn.write_all(req).unwrap();
+    let mut res = String::new();
+    conn.read_to_string(&mut res).unwrap();
+    res
+}
+
+/// POST / with a single file field, its filename parameter left out
+/// when None
+pub fn upload(port: u16, file_name: Option<&str>, body: &[u8]) -> String {
+    let boundary = "quickshare-test-boundary";
+    let disposition = match file_name {
+        Some(name) => format!("form-data; name=\"file\"; filename=\"{}\"", name),
+        None => "form-data; name=\"file\"".to_string(),
+    };
+    let mut form = format!(
+        "--{}\r\nContent-Disposition: {}\r\nContent-Type: application/octet-stream\r\n\r\n",
+        boundary, disposition
+    )
+    .into_bytes();
+    form.extend_from_slice(body);
+    form.extend_from_slice(format!("\r\n--{}--\r\n", boundary).as_bytes());
+
+    let mut req = format!(
+        "POST / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\
+         Content-Type: multipart/form-data; boundary={}\r\nContent-Length: {}\r\n\r\n",
+        boundary,
+        form.len()
+    )
+    .into_bytes();
+    req.extend_from_slice(&form);
+    request(port, &req)
+}
+
+/// names of the uploads stored in dir
+pub fn uploads(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.starts_with("quickshare_"))
+        .collect();
+    names.sort();
+    names
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    process::{Command, Stdio},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use common::{wait_for, Server};
+
+#[test]
+fn port_zero_is_written_to_the_port_file() {
+    let dir = std::env::temp_dir().join(format!("quickshare-port-file-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let port_file = dir.join("ports");
+    // left behind by an earlier run
+    std::fs::write(&port_file, "1\n").unwrap();
+
+    let server = Server(
+        Command::new(env!("CARGO_BIN_EXE_quickshare"))
+            .args(["-b", "127.0.0.1:0", "--no-warnings", "--port-file"])
+            .arg(&port_file)
+            .current_dir(&dir)
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    let start = Instant::now();
+    let port: u16 = loop {
+        let ports = wait_for(&port_file);
+        if ports != "1\n" {
+            break ports.trim().parse().unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_secs(10), "stale port file");
+        sleep(Duration::from_millis(20));
+    };
+    assert_ne!(port, 0);
+
+    let mut conn = TcpStream::connect(("127.0.0.1", port)).unwrap();
+    conn.write_all(b"GET /capabilities HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut res = String::new();
+    conn.read_to_string(&mut res).unwrap();
+    assert!(res.starts_with("HTTP/1.1 200"), "{}", res);
+
+    drop(server);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
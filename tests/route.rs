@@ -0,0 +1,18 @@
+mod common;
+
+use common::{spawn, temp_dir, upload};
+
+#[test]
+fn file_in_the_way_of_a_route_is_a_conflict() {
+    let dir = temp_dir("route-file-in-the-way");
+    std::fs::write(dir.join("a"), "not a directory").unwrap();
+    let (server, port) = spawn(&dir, &["--route-ext", "x=a/b"]);
+
+    let res = upload(port, Some("f.x"), b"hello");
+    assert!(res.starts_with("HTTP/1.1 409"), "{}", res);
+    assert!(res.contains("path component is a file"), "{}", res);
+    assert_eq!(std::fs::read(dir.join("a")).unwrap(), b"not a directory");
+
+    drop(server);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
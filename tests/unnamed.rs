@@ -0,0 +1,66 @@
+mod common;
+
+use common::{spawn, temp_dir, upload, uploads};
+
+#[test]
+fn untitled_names_missing_and_empty_file_names() {
+    let dir = temp_dir("unnamed-untitled");
+    let (server, port) = spawn(&dir, &["--unnamed-policy", "untitled"]);
+
+    let res = upload(port, None, b"first");
+    assert!(res.starts_with("HTTP/1.1 200"), "{}", res);
+    assert_eq!(uploads(&dir), ["quickshare_untitled"]);
+    // the same name again, which is already taken
+    let res = upload(port, Some(""), b"second");
+    assert!(res.starts_with("HTTP/1.1 409"), "{}", res);
+    assert_eq!(
+        std::fs::read(dir.join("quickshare_untitled")).unwrap(),
+        b"first"
+    );
+
+    drop(server);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reject_refuses_uploads_without_a_name() {
+    let dir = temp_dir("unnamed-reject");
+    let (server, port) = spawn(&dir, &["--unnamed-policy", "reject"]);
+
+    for file_name in [None, Some("")] {
+        let res = upload(port, file_name, b"hello");
+        assert!(res.starts_with("HTTP/1.1 400"), "{}", res);
+        assert!(res.ends_with("the file needs a name"), "{}", res);
+    }
+    assert!(uploads(&dir).is_empty());
+    // named ones are still fine
+    let res = upload(port, Some("named.txt"), b"hello");
+    assert!(res.starts_with("HTTP/1.1 200"), "{}", res);
+    assert_eq!(uploads(&dir), ["quickshare_named.txt"]);
+
+    drop(server);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn random_keeps_anonymous_uploads_apart() {
+    let dir = temp_dir("unnamed-random");
+    let (server, port) = spawn(&dir, &["--unnamed-policy", "random"]);
+
+    let res = upload(port, None, b"first");
+    assert!(res.starts_with("HTTP/1.1 200"), "{}", res);
+    let res = upload(port, Some(""), b"second");
+    assert!(res.starts_with("HTTP/1.1 200"), "{}", res);
+
+    let names = uploads(&dir);
+    assert_eq!(names.len(), 2, "{:?}", names);
+    let mut contents: Vec<Vec<u8>> = names
+        .iter()
+        .map(|name| std::fs::read(dir.join(name)).unwrap())
+        .collect();
+    contents.sort();
+    assert_eq!(contents, [b"first".to_vec(), b"second".to_vec()]);
+
+    drop(server);
+    std::fs::remove_dir_all(&dir).unwrap();
+}